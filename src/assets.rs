@@ -0,0 +1,173 @@
+//! Serving embedded static assets (the drawing web UI, icons, etc.) with
+//! conditional-GET caching. An asset's bytes are expected to come from
+//! `include_bytes!` and so never change at runtime, which is what makes a
+//! weak, length-plus-hash ETag good enough: no timestamps or strong
+//! hashing needed, just something that changes when the embedded bytes do.
+use core::fmt::Write;
+
+use crate::buffer::ResponseBuffer;
+use crate::write_response_status;
+
+/// One static asset: raw bytes (typically from `include_bytes!`) plus the
+/// `Content-Type` to serve them with.
+#[derive(Clone, Copy)]
+struct Asset {
+    path: &'static str,
+    content_type: &'static str,
+    bytes: &'static [u8],
+}
+
+/// A fixed-capacity table of static assets, keyed by path. `N` is the max
+/// number of assets it can hold; registrations past that are silently
+/// dropped, matching this crate's general tolerance for truncating
+/// oversized input instead of erroring on it.
+pub struct Assets<const N: usize> {
+    assets: [Option<Asset>; N],
+    count: usize,
+}
+
+impl<const N: usize> Default for Assets<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Assets<N> {
+    pub fn new() -> Self {
+        Self {
+            assets: [None; N],
+            count: 0,
+        }
+    }
+
+    /// Registers an asset at `path`. Returns `self` so registrations can be
+    /// chained, e.g. `assets.register(..).register(..)`.
+    pub fn register(
+        &mut self,
+        path: &'static str,
+        content_type: &'static str,
+        bytes: &'static [u8],
+    ) -> &mut Self {
+        if self.count < self.assets.len() {
+            self.assets[self.count] = Some(Asset {
+                path,
+                content_type,
+                bytes,
+            });
+            self.count += 1;
+        }
+        self
+    }
+
+    fn find(&self, path: &str) -> Option<Asset> {
+        self.assets[..self.count]
+            .iter()
+            .flatten()
+            .find(|asset| asset.path == path)
+            .copied()
+    }
+
+    /// Writes the response headers for a `GET` of `path` into
+    /// `response_buffer`, and returns the body the caller should stream
+    /// over the socket afterward: `Some(bytes)` for a fresh `200`, `None`
+    /// for a `304` (matched `if_none_match`) or a `404` (no such asset) -
+    /// either way, nothing more to send.
+    pub fn serve<const S: usize>(
+        &self,
+        path: &str,
+        if_none_match: Option<&str>,
+        response_buffer: &mut ResponseBuffer<S>,
+    ) -> Option<&'static [u8]> {
+        let Some(asset) = self.find(path) else {
+            write_response_status(response_buffer, 404);
+            let _ = write!(response_buffer, "\r\n");
+            return None;
+        };
+
+        let mut etag_buf = ResponseBuffer::<32>::new();
+        let _ = write!(
+            etag_buf,
+            "W/\"{:x}-{}\"",
+            fnv1a(asset.bytes),
+            asset.bytes.len()
+        );
+        let etag = core::str::from_utf8(etag_buf.buffer()).unwrap_or("");
+
+        if if_none_match == Some(etag) {
+            write_response_status(response_buffer, 304);
+            let _ = write!(response_buffer, "ETag: {}\r\n", etag);
+            let _ = write!(response_buffer, "\r\n");
+            return None;
+        }
+
+        write_response_status(response_buffer, 200);
+        let _ = write!(response_buffer, "Content-Type: {}\r\n", asset.content_type);
+        let _ = write!(
+            response_buffer,
+            "Content-Length: {}\r\n",
+            asset.bytes.len()
+        );
+        let _ = write!(response_buffer, "ETag: {}\r\n", etag);
+        let _ = write!(response_buffer, "\r\n");
+        Some(asset.bytes)
+    }
+}
+
+/// A tiny, dependency-free hash for weak ETags: good enough to notice a
+/// changed asset, not a cryptographic guarantee.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes
+        .iter()
+        .fold(OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unregistered_path_serves_404_and_no_body() {
+        let assets = Assets::<2>::new();
+        let mut response_buffer = ResponseBuffer::<128>::new();
+        let body = assets.serve("/missing", None, &mut response_buffer);
+        assert!(body.is_none());
+        assert!(core::str::from_utf8(response_buffer.buffer())
+            .unwrap()
+            .starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn fresh_request_serves_body_with_etag() {
+        let mut assets = Assets::<2>::new();
+        assets.register("/", "text/html", b"<html></html>");
+        let mut response_buffer = ResponseBuffer::<128>::new();
+        let body = assets.serve("/", None, &mut response_buffer);
+        assert_eq!(body, Some(&b"<html></html>"[..]));
+        let headers = core::str::from_utf8(response_buffer.buffer()).unwrap();
+        assert!(headers.starts_with("HTTP/1.1 200"));
+        assert!(headers.contains("ETag: W/\""));
+    }
+
+    #[test]
+    fn matching_if_none_match_serves_304_with_no_body() {
+        let mut assets = Assets::<2>::new();
+        assets.register("/", "text/html", b"<html></html>");
+
+        let mut probe = ResponseBuffer::<128>::new();
+        assets.serve("/", None, &mut probe);
+        let headers = core::str::from_utf8(probe.buffer()).unwrap();
+        let etag = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap();
+
+        let mut response_buffer = ResponseBuffer::<128>::new();
+        let body = assets.serve("/", Some(etag), &mut response_buffer);
+        assert!(body.is_none());
+        assert!(core::str::from_utf8(response_buffer.buffer())
+            .unwrap()
+            .starts_with("HTTP/1.1 304"));
+    }
+}
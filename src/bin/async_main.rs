@@ -1,9 +1,13 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+use alloc::boxed::Box;
+
 /// External imports
 use embassy_executor::Spawner;
-use embassy_net::{IpListenEndpoint, Stack, StackResources};
+use embassy_net::{IpListenEndpoint, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
 use embassy_time::{Duration, Timer};
 use esp_alloc as _;
@@ -22,13 +26,18 @@ use esp_hal::{
 use esp_println::println;
 use esp_wifi::{
     init,
-    wifi::{WifiDevice, WifiStaDevice},
+    wifi::{WifiApDevice, WifiDevice, WifiStaDevice},
     EspWifiController,
 };
 use static_cell::StaticCell;
 
-use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565, prelude::*};
+#[cfg(not(feature = "epaper"))]
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
+use embedded_graphics::prelude::*;
 use embedded_hal_bus::spi::ExclusiveDevice;
+#[cfg(feature = "epaper")]
+use ssd1680::driver::Ssd1680;
+#[cfg(not(feature = "epaper"))]
 use st7735_lcd::ST7735;
 
 /// Crate imports
@@ -48,6 +57,13 @@ macro_rules! mk_static {
 const SSID: &str = env!("SSID");
 const PASSWORD: &str = env!("PASSWORD");
 
+/// SSID the board advertises when it falls back to SoftAP mode.
+const AP_SSID: &str = "esp32-drawer";
+
+/// Static address the SoftAP interface answers on; phones joining the
+/// board's own network reach it here instead of via DHCP.
+const AP_GATEWAY: embassy_net::Ipv4Address = embassy_net::Ipv4Address::new(192, 168, 4, 1);
+
 const WEB_ENDPOINT: IpListenEndpoint = IpListenEndpoint {
     addr: None,
     port: 8080,
@@ -58,6 +74,39 @@ const BACKEND_ENDPOINT: IpListenEndpoint = IpListenEndpoint {
     port: 5000,
 };
 
+/// Fire-and-forget coordinate stream: no accept/close cycle, so strokes
+/// render with much lower latency than the TCP `/data` path.
+const UDP_ENDPOINT: IpListenEndpoint = IpListenEndpoint {
+    addr: None,
+    port: 5001,
+};
+
+/// The only origin allowed to make credentialed `POST /data` requests over
+/// the SoftAP stack: the web UI served from this same board's
+/// `WEB_ENDPOINT`, reached via the AP's fixed address.
+const AP_CORS: esp32_drawer::CorsConfig = esp32_drawer::CorsConfig {
+    allowed_origins: &["http://192.168.4.1:8080"],
+    allowed_methods: "GET, POST, OPTIONS",
+    allowed_headers: "Content-Type",
+    max_age: 86400,
+};
+
+/// Builds a CORS config scoped to a DHCP-assigned stack's actual resolved
+/// address (STA, W5500), which isn't known until runtime. `AP_CORS` can be
+/// a `const` because the SoftAP's address is fixed; this can't, so its
+/// pieces are leaked to `'static` instead.
+fn cors_for(address: embassy_net::Ipv4Address, port: u16) -> &'static esp32_drawer::CorsConfig<'static> {
+    let origin: &'static str =
+        Box::leak(alloc::format!("http://{}:{}", address, port).into_boxed_str());
+    let allowed_origins: &'static [&'static str] = Box::leak(Box::new([origin]));
+    Box::leak(Box::new(esp32_drawer::CorsConfig {
+        allowed_origins,
+        allowed_methods: "GET, POST, OPTIONS",
+        allowed_headers: "Content-Type",
+        max_age: 86400,
+    }))
+}
+
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) -> ! {
     esp_println::logger::init_logger_from_env();
@@ -67,15 +116,24 @@ async fn main(spawner: Spawner) -> ! {
 
     esp_alloc::heap_allocator!(72 * 1024);
 
+    #[cfg(not(feature = "epaper"))]
     let sclk = peripherals.GPIO5;
+    #[cfg(not(feature = "epaper"))]
     let miso = peripherals.GPIO19;
+    #[cfg(not(feature = "epaper"))]
     let mosi = peripherals.GPIO18;
+    #[cfg(not(feature = "epaper"))]
     let cs = Output::new(peripherals.GPIO16, Level::High);
+    #[cfg(not(feature = "epaper"))]
     let dc = Output::new(peripherals.GPIO17, Level::High);
+    #[cfg(not(feature = "epaper"))]
     let rst = Output::new(peripherals.GPIO21, Level::High);
+    #[cfg(not(feature = "epaper"))]
     let mut lcd_led = Output::new(peripherals.GPIO14, Level::High);
+    #[cfg(not(feature = "epaper"))]
     lcd_led.set_high();
 
+    #[cfg(not(feature = "epaper"))]
     let spi = Spi::new_with_config(
         peripherals.SPI2,
         Config {
@@ -89,77 +147,288 @@ async fn main(spawner: Spawner) -> ! {
     .with_miso(miso)
     .into_async();
 
+    #[cfg(not(feature = "epaper"))]
     let spi_device = ExclusiveDevice::new_no_delay(spi, cs).unwrap();
 
+    #[cfg(not(feature = "epaper"))]
     let mut start_screen_task = true;
+    #[cfg(not(feature = "epaper"))]
     let mut delay = Delay::new();
+    #[cfg(not(feature = "epaper"))]
     let mut st7735 = ST7735::new(spi_device, dc, rst, true, false, 160, 128);
+    #[cfg(not(feature = "epaper"))]
     let initialize = st7735.init(&mut delay);
+    #[cfg(not(feature = "epaper"))]
     let orientation = st7735.set_orientation(&st7735_lcd::Orientation::Landscape);
+    #[cfg(not(feature = "epaper"))]
     let _cleared = st7735.clear(Rgb565::BLACK);
 
+    #[cfg(not(feature = "epaper"))]
     if initialize.is_err() || orientation.is_err() {
         start_screen_task = false;
     }
 
-    let timg0 = TimerGroup::new(peripherals.TIMG0);
-    let mut rng = Rng::new(peripherals.RNG);
+    // Same SPI bus and pins as the ST7735 above: only one panel is ever
+    // physically wired up, chosen at build time by this feature, so they're
+    // never both live at once. The panel's BUSY line (absent on the ST7735)
+    // gets the one GPIO neither screen nor `w5500` otherwise claims.
+    #[cfg(feature = "epaper")]
+    let epaper_sclk = peripherals.GPIO5;
+    #[cfg(feature = "epaper")]
+    let epaper_miso = peripherals.GPIO19;
+    #[cfg(feature = "epaper")]
+    let epaper_mosi = peripherals.GPIO18;
+    #[cfg(feature = "epaper")]
+    let epaper_cs = Output::new(peripherals.GPIO16, Level::High);
+    #[cfg(feature = "epaper")]
+    let epaper_dc = Output::new(peripherals.GPIO17, Level::High);
+    #[cfg(feature = "epaper")]
+    let epaper_rst = Output::new(peripherals.GPIO21, Level::High);
+    #[cfg(feature = "epaper")]
+    let epaper_busy = esp_hal::gpio::Input::new(peripherals.GPIO15, esp_hal::gpio::Pull::None);
+
+    #[cfg(feature = "epaper")]
+    let epaper_spi = Spi::new_with_config(
+        peripherals.SPI2,
+        Config {
+            frequency: 16000.kHz(),
+            mode: SpiMode::Mode0,
+            ..Config::default()
+        },
+    )
+    .with_sck(epaper_sclk)
+    .with_mosi(epaper_mosi)
+    .with_miso(epaper_miso)
+    .into_async();
 
-    let init = &*mk_static!(
-        EspWifiController<'static>,
-        init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
-    );
+    #[cfg(feature = "epaper")]
+    let epaper_spi_device = ExclusiveDevice::new_no_delay(epaper_spi, epaper_cs).unwrap();
 
-    let wifi = peripherals.WIFI;
-    let (wifi_interface, controller) =
-        esp_wifi::wifi::new_with_mode(init, wifi, WifiStaDevice).unwrap();
+    #[cfg(feature = "epaper")]
+    let mut delay = Delay::new();
+    #[cfg(feature = "epaper")]
+    let mut ssd1680 = Ssd1680::new(
+        epaper_spi_device,
+        epaper_dc,
+        epaper_rst,
+        epaper_busy,
+        &mut delay,
+    )
+    .unwrap();
+    #[cfg(feature = "epaper")]
+    ssd1680.init(&mut delay).unwrap();
+    #[cfg(feature = "epaper")]
+    let epaper_panel = tasks::epaper::EpaperScreen::new(ssd1680);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let mut rng = Rng::new(peripherals.RNG);
 
     let timg1 = TimerGroup::new(peripherals.TIMG1);
     esp_hal_embassy::init(timg1.timer0);
 
-    let config = embassy_net::Config::dhcpv4(Default::default());
-
     let seed = (rng.random() as u64) << 32 | rng.random() as u64;
 
-    // Init network stack
-    let stack = &*mk_static!(
-        Stack<WifiDevice<'_, WifiStaDevice>>,
-        Stack::new(
-            wifi_interface,
-            config,
-            mk_static!(StackResources<4>, StackResources::<4>::new()),
-            seed
-        )
-    );
+    type SignalType = StaticCell<Signal<NoopRawMutex, ScreenSignal>>;
+    static SIGNAL: SignalType = StaticCell::new();
+    let signal = &*SIGNAL.init(Signal::new());
 
-    spawner.spawn(tasks::connection(controller)).ok();
-    spawner.spawn(tasks::net_task(stack)).ok();
+    // Shared across `backend` (TCP `/data`, `/clear`, WebSocket strokes) and
+    // `espnow` (coordinates mirrored in from a peer board), so a point
+    // drawn by either source shows up in a subsequent `GET /data`.
+    type GridDataType = StaticCell<tasks::backend::SharedGridData>;
+    static GRID_DATA: GridDataType = StaticCell::new();
+    let grid_data = &*GRID_DATA.init(tasks::backend::SharedGridData::new(
+        tasks::backend::GridData::new(),
+    ));
 
-    loop {
-        if stack.is_link_up() {
-            break;
+    #[cfg(not(feature = "w5500"))]
+    {
+        let init = &*mk_static!(
+            EspWifiController<'static>,
+            init(timg0.timer0, rng, peripherals.RADIO_CLK).unwrap()
+        );
+
+        let wifi = peripherals.WIFI;
+        let (ap_interface, wifi_interface, controller) =
+            esp_wifi::wifi::new_ap_sta(init, wifi).unwrap();
+
+        let config = embassy_net::Config::dhcpv4(Default::default());
+
+        // Init STA network stack
+        let stack = &*mk_static!(
+            Stack<WifiDevice<'_, WifiStaDevice>>,
+            Stack::new(
+                wifi_interface,
+                config,
+                mk_static!(StackResources<4>, StackResources::<4>::new()),
+                seed
+            )
+        );
+
+        // Init SoftAP network stack with a static address; it only comes up
+        // once `tasks::connection` falls back to `Configuration::Mixed`.
+        let ap_config = embassy_net::Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(AP_GATEWAY, 24),
+            gateway: Some(AP_GATEWAY),
+            dns_servers: Default::default(),
+        });
+        let ap_stack = &*mk_static!(
+            Stack<WifiDevice<'_, WifiApDevice>>,
+            Stack::new(
+                ap_interface,
+                ap_config,
+                mk_static!(StackResources<4>, StackResources::<4>::new()),
+                seed
+            )
+        );
+
+        // ESP-NOW shares the same radio as the STA/AP controller, so it
+        // rides alongside the Wi-Fi stacks above instead of needing its
+        // own peripheral.
+        let esp_now = esp_wifi::esp_now::EspNow::new(init, &controller).unwrap();
+        let espnow_origin = (seed & 0xff) as u8;
+
+        type EspNowOutboundType = StaticCell<tasks::espnow::OutboundChannel>;
+        static ESP_NOW_OUTBOUND: EspNowOutboundType = StaticCell::new();
+        let espnow_outbound = &*ESP_NOW_OUTBOUND.init(tasks::espnow::OutboundChannel::new());
+
+        spawner.spawn(tasks::connection(controller)).ok();
+        spawner.spawn(tasks::net_task(stack)).ok();
+        spawner.spawn(tasks::net_task_ap(ap_stack)).ok();
+
+        loop {
+            if stack.is_link_up() {
+                break;
+            }
+            Timer::after(Duration::from_millis(500)).await;
         }
-        Timer::after(Duration::from_millis(500)).await;
+
+        println!("Waiting to get IP address...\r\n");
+        let sta_address = loop {
+            if let Some(config) = stack.config_v4() {
+                println!("Got IP: {}\r\n", config.address);
+                break config.address.address();
+            }
+            Timer::after(Duration::from_millis(500)).await;
+        };
+        let sta_cors = cors_for(sta_address, WEB_ENDPOINT.port);
+
+        spawner.spawn(tasks::web::task_loop(stack)).ok();
+        spawner
+            .spawn(tasks::backend::task_loop(
+                stack,
+                signal,
+                espnow_outbound,
+                grid_data,
+                sta_cors,
+            ))
+            .ok();
+        spawner.spawn(tasks::web::task_loop_ap(ap_stack)).ok();
+        spawner
+            .spawn(tasks::backend::task_loop_ap(
+                ap_stack,
+                signal,
+                espnow_outbound,
+                grid_data,
+                &AP_CORS,
+            ))
+            .ok();
+        spawner
+            .spawn(tasks::espnow::task_loop(
+                esp_now,
+                espnow_origin,
+                espnow_outbound,
+                signal,
+                grid_data,
+            ))
+            .ok();
     }
 
-    println!("Waiting to get IP address...\r\n");
-    loop {
-        if let Some(config) = stack.config_v4() {
-            println!("Got IP: {}\r\n", config.address);
-            break;
+    #[cfg(feature = "w5500")]
+    {
+        let eth_sclk = peripherals.GPIO12;
+        let eth_miso = peripherals.GPIO13;
+        let eth_mosi = peripherals.GPIO11;
+        let eth_cs = Output::new(peripherals.GPIO10, Level::High);
+        let eth_int = esp_hal::gpio::Input::new(peripherals.GPIO9, esp_hal::gpio::Pull::Up);
+        let eth_reset = Output::new(peripherals.GPIO8, Level::High);
+
+        let eth_spi = Spi::new_with_config(
+            peripherals.SPI3,
+            Config {
+                frequency: 16000.kHz(),
+                mode: SpiMode::Mode0,
+                ..Config::default()
+            },
+        )
+        .with_sck(eth_sclk)
+        .with_mosi(eth_mosi)
+        .with_miso(eth_miso)
+        .into_async();
+        let eth_spi_device = ExclusiveDevice::new_no_delay(eth_spi, eth_cs).unwrap();
+
+        let mac_addr = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+        let w5500_state = mk_static!(
+            embassy_net_w5500::State<8, 8>,
+            embassy_net_w5500::State::new()
+        );
+        let (device, runner) =
+            tasks::w5500::init(eth_spi_device, eth_int, eth_reset, mac_addr, w5500_state).await;
+
+        let config = embassy_net::Config::dhcpv4(Default::default());
+        let stack = &*mk_static!(
+            Stack<tasks::w5500::W5500Device>,
+            Stack::new(
+                device,
+                config,
+                mk_static!(StackResources<4>, StackResources::<4>::new()),
+                seed
+            )
+        );
+
+        type EspNowOutboundType = StaticCell<tasks::espnow::OutboundChannel>;
+        static ESP_NOW_OUTBOUND: EspNowOutboundType = StaticCell::new();
+        let espnow_outbound = &*ESP_NOW_OUTBOUND.init(tasks::espnow::OutboundChannel::new());
+
+        spawner.spawn(tasks::w5500::net_task(runner)).ok();
+
+        loop {
+            if stack.is_link_up() {
+                break;
+            }
+            Timer::after(Duration::from_millis(500)).await;
         }
-        Timer::after(Duration::from_millis(500)).await;
-    }
 
-    type SignalType = StaticCell<Signal<NoopRawMutex, ScreenSignal>>;
-    static SIGNAL: SignalType = StaticCell::new();
-    let signal = &*SIGNAL.init(Signal::new());
+        println!("Waiting to get IP address...\r\n");
+        let eth_address = loop {
+            if let Some(config) = stack.config_v4() {
+                println!("Got IP: {}\r\n", config.address);
+                break config.address.address();
+            }
+            Timer::after(Duration::from_millis(500)).await;
+        };
+        let eth_cors = cors_for(eth_address, WEB_ENDPOINT.port);
+
+        spawner.spawn(tasks::web::task_loop_eth(stack)).ok();
+        spawner
+            .spawn(tasks::backend::task_loop_eth(
+                stack,
+                signal,
+                espnow_outbound,
+                grid_data,
+                eth_cors,
+            ))
+            .ok();
+    }
 
-    spawner.spawn(tasks::web::task_loop(stack)).ok();
-    spawner.spawn(tasks::backend::task_loop(stack, signal)).ok();
+    #[cfg(not(feature = "epaper"))]
     if start_screen_task {
         spawner.spawn(tasks::screen::task_loop(st7735, signal)).ok();
     }
+    #[cfg(feature = "epaper")]
+    spawner
+        .spawn(tasks::epaper::task_loop(epaper_panel, signal))
+        .ok();
 
     loop {
         Timer::after(Duration::from_millis(500)).await;
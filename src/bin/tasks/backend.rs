@@ -1,35 +1,212 @@
 /// Core imports
+use alloc::boxed::Box;
 use core::fmt::Write;
-use embassy_net::{tcp::TcpSocket, Stack};
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_futures::select::{select, Either};
+use embassy_net::{driver::Driver, tcp::TcpSocket, udp::{PacketMetadata, UdpSocket}, Stack};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex, signal::Signal};
 use embassy_time::{Duration, Timer};
 // use embedded_io_async::Write as EmbeddedIoWrite;
 use esp_println::println;
-use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
+use esp_wifi::wifi::{WifiApDevice, WifiDevice, WifiStaDevice};
 use serde::{Deserialize, Serialize, Serializer};
 
 /// Crate imports
-use crate::BACKEND_ENDPOINT;
+use crate::{BACKEND_ENDPOINT, UDP_ENDPOINT};
 use esp32_drawer::buffer::{RequestBuffer, ResponseBuffer};
 use esp32_drawer::close_socket;
 use esp32_drawer::get_request;
+use esp32_drawer::router::{HandlerFuture, Router};
 use esp32_drawer::send_response_buffer;
+use esp32_drawer::udp::{decode_frame, Frame};
+use esp32_drawer::write_preflight_response;
 use esp32_drawer::write_response_headers;
 use esp32_drawer::write_response_status;
+use esp32_drawer::ColoredCoordinate;
+use esp32_drawer::CorsConfig;
 use esp32_drawer::Coordinates;
+use esp32_drawer::EspNowOutbound;
+use esp32_drawer::ParseStatus;
 use esp32_drawer::Request;
+use esp32_drawer::RequestError;
+use esp32_drawer::websocket::Opcode;
 use esp32_drawer::ScreenSignal;
+use esp32_drawer::ESP_NOW_COORDS_PER_FRAME;
 
-struct GridData {
+use crate::tasks::espnow::OutboundChannel;
+use crate::tasks::ws;
+
+/// Size of the request/response buffers `backend::serve` hands to the
+/// router; kept as named constants so `serve` and the `Router`/`Handler`
+/// types below state the sizes only once.
+const REQ_BUF: usize = 512;
+const RESP_BUF: usize = 1024;
+
+/// State a route handler may need that a plain `fn` pointer can't capture
+/// as a closure: the shared grid, the screen signal, the ESP-NOW outbound
+/// queue, and the CORS policy for the stack this request arrived on.
+#[derive(Clone, Copy)]
+struct Ctx {
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+    grid_data: &'static SharedGridData,
+    cors: &'static CorsConfig<'static>,
+}
+
+fn backend_router() -> Router<Ctx, (), REQ_BUF, RESP_BUF, 4> {
+    let mut router = Router::new();
+    router
+        .route("GET", "/data", handle_get_data)
+        .route("POST", "/data", handle_post_data)
+        .route("POST", "/clear", handle_post_clear)
+        .route("OPTIONS", "*", handle_preflight);
+    router
+}
+
+fn handle_get_data<'a>(
+    request: Request<'a, REQ_BUF>,
+    response_buffer: &'a mut ResponseBuffer<RESP_BUF>,
+    ctx: Ctx,
+) -> HandlerFuture<'a, ()> {
+    Box::pin(async move {
+        let origin = request.header("Origin");
+        let mut coordinates = CoordinateList::new();
+        let mut position = 0;
+        {
+            let grid = ctx.grid_data.lock().await;
+            for (r_idx, row) in grid.data.iter().enumerate() {
+                for (c_idx, col) in row.iter().enumerate() {
+                    if *col != 0 && position < coordinates.coords.len() {
+                        coordinates.coords[position] = Some(ColoredCoordinate {
+                            row: r_idx,
+                            col: c_idx,
+                            color: grid.colors[r_idx][c_idx],
+                        });
+                        position += 1;
+                    }
+                }
+            }
+        }
+        let mut buffer = [0; 2048];
+        match serde_json_core::to_slice(&coordinates, &mut buffer[..]) {
+            Ok(len) => {
+                write_response_status(response_buffer, 200);
+                let _ = write!(response_buffer, "Content-Type: application/json\r\n");
+                let _ = write!(response_buffer, "Content-Length: {}\r\n", len);
+                write_response_headers(response_buffer, ctx.cors, origin);
+                let _ = response_buffer.write(&buffer[..len]);
+            }
+            Err(e) => {
+                println!("{:?}", e);
+                write_response_status(response_buffer, 500);
+                write_response_headers(response_buffer, ctx.cors, origin);
+            }
+        }
+    })
+}
+
+fn handle_post_data<'a>(
+    request: Request<'a, REQ_BUF>,
+    response_buffer: &'a mut ResponseBuffer<RESP_BUF>,
+    ctx: Ctx,
+) -> HandlerFuture<'a, ()> {
+    Box::pin(async move {
+        let origin = request.header("Origin");
+        let body = core::str::from_utf8(request.data.unwrap_or(&[])).unwrap_or("");
+        match serde_json_core::from_str::<Coordinates>(body) {
+            Ok(result) => {
+                let coord_list = result.0;
+                ctx.signal.signal(ScreenSignal::Coordinate(coord_list));
+                {
+                    let mut grid = ctx.grid_data.lock().await;
+                    for coordinate in coord_list.iter().flatten() {
+                        grid.set(*coordinate);
+                    }
+                }
+                broadcast_coordinates(ctx.espnow_outbound, coord_list.iter().flatten().copied())
+                    .await;
+            }
+            Err(e) => {
+                println!("Error converting coordinates: {:?}", e);
+            }
+        }
+        write_response_status(response_buffer, 200);
+        write_response_headers(response_buffer, ctx.cors, origin);
+    })
+}
+
+fn handle_post_clear<'a>(
+    request: Request<'a, REQ_BUF>,
+    response_buffer: &'a mut ResponseBuffer<RESP_BUF>,
+    ctx: Ctx,
+) -> HandlerFuture<'a, ()> {
+    Box::pin(async move {
+        let origin = request.header("Origin");
+        ctx.grid_data.lock().await.clear();
+        ctx.signal.signal(ScreenSignal::Clear);
+        let _ = ctx.espnow_outbound.try_send(EspNowOutbound::Clear);
+        write_response_status(response_buffer, 200);
+        write_response_headers(response_buffer, ctx.cors, origin);
+    })
+}
+
+fn handle_preflight<'a>(
+    request: Request<'a, REQ_BUF>,
+    response_buffer: &'a mut ResponseBuffer<RESP_BUF>,
+    ctx: Ctx,
+) -> HandlerFuture<'a, ()> {
+    Box::pin(async move {
+        let origin = request.header("Origin");
+        write_preflight_response(response_buffer, ctx.cors, origin);
+    })
+}
+
+/// The drawing grid, shared between `backend` (TCP `/data`, `/clear`, and
+/// WebSocket strokes) and `espnow` (coordinates mirrored in from a peer
+/// board), so every source of truth for "what's drawn" agrees: a point
+/// that arrived over ESP-NOW shows up in a subsequent `GET /data` exactly
+/// like one that arrived over TCP or UDP.
+pub(crate) type SharedGridData = Mutex<NoopRawMutex, GridData>;
+
+pub(crate) struct GridData {
+    /// 0/1 "is set" flag per cell, kept alongside `colors` so a lookup
+    /// doesn't need a sentinel color to mean "unset".
     data: [[u8; 80]; 64],
+    colors: [[u16; 80]; 64],
+}
+
+impl Default for GridData {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-type Coordinate = (usize, usize);
+impl GridData {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: [[0; 80]; 64],
+            colors: [[0; 80]; 64],
+        }
+    }
+
+    /// Coordinates from the JSON `/data` body, UDP or ESP-NOW aren't range
+    /// checked on the way in; any outside the 80x64 grid are ignored here.
+    pub(crate) fn set(&mut self, coordinate: ColoredCoordinate) {
+        if coordinate.row < self.data.len() && coordinate.col < self.data[0].len() {
+            self.data[coordinate.row][coordinate.col] = 1;
+            self.colors[coordinate.row][coordinate.col] = coordinate.color;
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.data = [[0; 80]; 64];
+        self.colors = [[0; 80]; 64];
+    }
+}
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct CoordinateList {
     #[serde(serialize_with = "ignore_none")]
-    coords: serde_big_array::Array<Option<Coordinate>, 256>,
+    coords: serde_big_array::Array<Option<ColoredCoordinate>, 256>,
 }
 
 impl CoordinateList {
@@ -40,7 +217,10 @@ impl CoordinateList {
     }
 }
 
-fn ignore_none<S>(array: &[Option<Coordinate>; 256], serializer: S) -> Result<S::Ok, S::Error>
+fn ignore_none<S>(
+    array: &[Option<ColoredCoordinate>; 256],
+    serializer: S,
+) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -48,23 +228,199 @@ where
     serializer.collect_seq(filtered_array)
 }
 
+/// Decodes one UDP coordinate datagram and applies it directly to the
+/// grid/screen/ESP-NOW state, with no TCP connection setup or teardown.
+async fn handle_udp_datagram(
+    recv: Result<(usize, embassy_net::udp::UdpMetadata), embassy_net::udp::RecvError>,
+    datagram: &[u8],
+    grid_data: &'static SharedGridData,
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+) {
+    let (len, _meta) = match recv {
+        Ok(ok) => ok,
+        Err(e) => {
+            println!("udp recv error: {:?}\r\n", e);
+            return;
+        }
+    };
+
+    apply_coordinate_frame(&datagram[..len], grid_data, signal, espnow_outbound).await;
+}
+
+/// Decodes a coordinate-frame datagram (the same encoding used by the UDP
+/// path, and by WebSocket Binary messages) and applies it to the
+/// grid/screen/ESP-NOW state.
+async fn apply_coordinate_frame(
+    datagram: &[u8],
+    grid_data: &'static SharedGridData,
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+) {
+    match decode_frame(datagram) {
+        Ok(Frame::Clear) => {
+            grid_data.lock().await.clear();
+            signal.signal(ScreenSignal::Clear);
+            let _ = espnow_outbound.try_send(EspNowOutbound::Clear);
+        }
+        Ok(Frame::Coordinates(coords)) => {
+            let mut batch = [None; 256];
+            let mut position = 0;
+            {
+                let mut grid = grid_data.lock().await;
+                for coordinate in coords {
+                    grid.set(coordinate);
+                    if position < batch.len() {
+                        batch[position] = Some(coordinate);
+                        position += 1;
+                    }
+                }
+            }
+            signal.signal(ScreenSignal::Coordinate(serde_big_array::Array(batch)));
+            broadcast_coordinates(espnow_outbound, batch.into_iter().flatten()).await;
+        }
+        Err(e) => {
+            println!("coordinate frame error: {:?}\r\n", e);
+        }
+    }
+}
+
+/// Takes over an upgraded socket and speaks the WebSocket protocol until the
+/// client closes or the connection errors: inbound Binary messages carry the
+/// same coordinate-frame encoding as the UDP path, so a client can stream
+/// strokes without polling `GET /data`.
+async fn serve_websocket(
+    socket: &mut TcpSocket<'_>,
+    grid_data: &'static SharedGridData,
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+) {
+    let mut buffer = [0u8; 1024];
+    loop {
+        match ws::read_message(socket, &mut buffer).await {
+            Ok(Some((Opcode::Binary, len))) => {
+                apply_coordinate_frame(&buffer[..len], grid_data, signal, espnow_outbound).await;
+            }
+            Ok(Some(_)) | Ok(None) => {}
+            Err(e) => {
+                println!("ws serve error: {:?}\r\n", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Chunks a coordinate batch into ESP-NOW-sized frames and queues them for
+/// the `espnow` task to broadcast. Drops a chunk rather than blocking the
+/// HTTP handler if the outbound channel is already full.
+async fn broadcast_coordinates(
+    outbound: &'static OutboundChannel,
+    coords: impl Iterator<Item = ColoredCoordinate>,
+) {
+    let mut chunk = [None; ESP_NOW_COORDS_PER_FRAME];
+    let mut len = 0;
+    for coordinate in coords {
+        chunk[len] = Some(coordinate);
+        len += 1;
+        if len == chunk.len() {
+            let _ = outbound.try_send(EspNowOutbound::Coordinates(chunk));
+            chunk = [None; ESP_NOW_COORDS_PER_FRAME];
+            len = 0;
+        }
+    }
+    if len > 0 {
+        let _ = outbound.try_send(EspNowOutbound::Coordinates(chunk));
+    }
+}
+
 #[embassy_executor::task]
 pub async fn task_loop(
     stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
     signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+    grid_data: &'static SharedGridData,
+    cors: &'static CorsConfig<'static>,
+) {
+    serve(stack, signal, espnow_outbound, grid_data, cors).await
+}
+
+/// Same JSON backend bound to the SoftAP stack, so the board can accept
+/// drawing updates directly from a phone joined to its own access point.
+#[embassy_executor::task]
+pub async fn task_loop_ap(
+    stack: &'static Stack<WifiDevice<'static, WifiApDevice>>,
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+    grid_data: &'static SharedGridData,
+    cors: &'static CorsConfig<'static>,
+) {
+    serve(stack, signal, espnow_outbound, grid_data, cors).await
+}
+
+/// Same JSON backend bound to a wired W5500 stack, for installations where
+/// Wi-Fi (and so ESP-NOW mirroring) isn't available.
+#[cfg(feature = "w5500")]
+#[embassy_executor::task]
+pub async fn task_loop_eth(
+    stack: &'static Stack<super::w5500::W5500Device>,
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+    grid_data: &'static SharedGridData,
+    cors: &'static CorsConfig<'static>,
+) {
+    serve(stack, signal, espnow_outbound, grid_data, cors).await
+}
+
+async fn serve<D: Driver>(
+    stack: &'static Stack<D>,
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    espnow_outbound: &'static OutboundChannel,
+    grid_data: &'static SharedGridData,
+    cors: &'static CorsConfig<'static>,
 ) {
     println!("Starting backend_loop\r\n");
+    let router = backend_router();
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
     let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
     socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
 
-    let mut grid_data = GridData {
-        data: [[0; 80]; 64],
-    };
+    let mut udp_rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut udp_rx_buffer = [0; 512];
+    let mut udp_tx_meta = [PacketMetadata::EMPTY; 8];
+    let mut udp_tx_buffer = [0; 512];
+    let mut udp_socket = UdpSocket::new(
+        stack,
+        &mut udp_rx_meta,
+        &mut udp_rx_buffer,
+        &mut udp_tx_meta,
+        &mut udp_tx_buffer,
+    );
+    if let Err(e) = udp_socket.bind(UDP_ENDPOINT) {
+        println!("udp bind error: {:?}\r\n", e);
+    }
+    let mut udp_datagram = [0u8; 512];
 
     loop {
-        let r = socket.accept(BACKEND_ENDPOINT).await;
+        let r = match select(
+            socket.accept(BACKEND_ENDPOINT),
+            udp_socket.recv_from(&mut udp_datagram),
+        )
+        .await
+        {
+            Either::First(r) => r,
+            Either::Second(recv) => {
+                handle_udp_datagram(
+                    recv,
+                    &udp_datagram,
+                    grid_data,
+                    signal,
+                    espnow_outbound,
+                )
+                .await;
+                continue;
+            }
+        };
 
         if let Err(e) = r {
             // close the socket if it is in at invalid state
@@ -73,95 +429,84 @@ pub async fn task_loop(
             continue;
         }
 
-        let mut request_buffer = RequestBuffer::<512>::new();
-        let mut response_buffer = ResponseBuffer::<1024>::new();
-        if let Err(e) = get_request(&mut socket, &mut request_buffer).await {
-            println!("backend_loop: {:?}", e);
-            continue;
-        }
-
-        let mut request: Request<512> = Request::new();
-        request.set_request_buffer(&request_buffer);
-        request.parse_request();
-
-        // println!("backend_loop: {:?} {:?}\r\n", request.method, request.path);
-
-        match request.method {
-            Some("GET") => match request.path {
-                Some("/data") => {
-                    let mut coordinates = CoordinateList::new();
-                    let mut position = 0;
-                    for (r_idx, row) in grid_data.data.iter().enumerate() {
-                        for (c_idx, col) in row.iter().enumerate() {
-                            if *col != 0 && position < coordinates.coords.len() {
-                                coordinates.coords[position] = Some((r_idx, c_idx));
-                                position += 1;
-                            }
-                        }
-                    }
-                    let mut buffer = [0; 2048];
-                    match serde_json_core::to_slice(&coordinates, &mut buffer[..]) {
-                        Ok(len) => {
-                            write_response_status(&mut response_buffer, 200);
-                            let _ =
-                                write!(&mut response_buffer, "Content-Type: application/json\r\n");
-                            let _ = write!(&mut response_buffer, "Content-Length: {}\r\n", len);
-                            write_response_headers(&mut response_buffer);
-                            let _ = response_buffer.write(&buffer[..len]);
-                            // println!("Bytes converted: {:?}\r\n", len);
-                        }
-                        Err(e) => {
-                            println!("{:?}", e);
-                            write_response_status(&mut response_buffer, 500);
-                            write_response_headers(&mut response_buffer);
-                        }
-                    }
+        // Reuse this socket for multiple requests until the client asks to
+        // close, or it goes quiet for longer than `get_request`'s header
+        // timeout.
+        loop {
+            let mut request_buffer = RequestBuffer::<REQ_BUF>::new();
+            let mut response_buffer = ResponseBuffer::<RESP_BUF>::new();
+            let request_len = match get_request(&mut socket, &mut request_buffer).await {
+                Ok(len) => len,
+                Err(RequestError::Timeout) => {
+                    write_response_status(&mut response_buffer, 408);
+                    write_response_headers(&mut response_buffer, cors, None);
+                    send_response_buffer(&mut socket, response_buffer).await;
+                    break;
                 }
-                _ => {
-                    write_response_status(&mut response_buffer, 404);
-                    write_response_headers(&mut response_buffer);
+                Err(e) => {
+                    println!("backend_loop: {:?}", e);
+                    break;
                 }
-            },
-            Some("POST") => match request.path {
-                Some("/data") => {
-                    match serde_json_core::from_str::<Coordinates>(request.data.unwrap()) {
-                        Ok(result) => {
-                            let coord_list = result.0;
-                            signal.signal(ScreenSignal::Coordinate(coord_list));
-                            for coordinate in coord_list.iter().flatten() {
-                                grid_data.data[coordinate.0][coordinate.1] = 1;
-                            }
-                        }
-                        Err(e) => {
-                            println!("Error converting coordinates: {:?}", e);
+            };
+
+            let mut request: Request<REQ_BUF> = Request::new();
+            request.set_request_buffer(&request_buffer, request_len);
+            if matches!(request.parse_request(), ParseStatus::Partial) {
+                println!("backend_loop: incomplete or malformed request headers\r\n");
+                break;
+            }
+            let origin = request.header("Origin");
+
+            // println!("backend_loop: {:?} {:?}\r\n", request.method, request.path);
+
+            if request.method == Some("GET") && request.path == Some("/ws") {
+                match request.websocket_key {
+                    Some(client_key) => {
+                        ws::prepare_handshake(client_key, &mut response_buffer);
+                        send_response_buffer(&mut socket, response_buffer).await;
+                        let r = socket.flush().await;
+                        if let Err(e) = r {
+                            println!("AP flush error: {:?}\r\n", e);
                         }
+                        serve_websocket(&mut socket, grid_data, signal, espnow_outbound).await;
+                    }
+                    None => {
+                        write_response_status(&mut response_buffer, 404);
+                        write_response_headers(&mut response_buffer, cors, origin);
+                        send_response_buffer(&mut socket, response_buffer).await;
                     }
-                    write_response_status(&mut response_buffer, 200);
-                    write_response_headers(&mut response_buffer);
-                }
-                Some("/clear") => {
-                    grid_data.data = [[0; 80]; 64];
-                    signal.signal(ScreenSignal::Clear);
-                    write_response_status(&mut response_buffer, 200);
-                    write_response_headers(&mut response_buffer);
-                }
-                _ => {
-                    write_response_status(&mut response_buffer, 404);
-                    write_response_headers(&mut response_buffer);
                 }
-            },
-            _ => {
+                break;
+            }
+
+            let keep_alive = request
+                .header("Connection")
+                .map(|value| !value.eq_ignore_ascii_case("close"))
+            .unwrap_or(true);
+
+            let ctx = Ctx {
+                signal,
+                espnow_outbound,
+                grid_data,
+                cors,
+            };
+            if router.dispatch(request, &mut response_buffer, ctx).await.is_none() {
                 write_response_status(&mut response_buffer, 404);
-                write_response_headers(&mut response_buffer);
+                write_response_headers(&mut response_buffer, cors, origin);
             }
-        }
 
-        send_response_buffer(&mut socket, response_buffer).await;
+            send_response_buffer(&mut socket, response_buffer).await;
 
-        let r = socket.flush().await;
-        if let Err(e) = r {
-            println!("AP flush error: {:?}\r\n", e);
+            let r = socket.flush().await;
+            if let Err(e) = r {
+                println!("AP flush error: {:?}\r\n", e);
+            }
+
+            if !keep_alive {
+                break;
+            }
         }
+
         Timer::after(Duration::from_millis(50)).await;
         socket.close();
         Timer::after(Duration::from_millis(50)).await;
@@ -0,0 +1,91 @@
+//! SSD1680 e-paper panel, wired up behind the `epaper` feature as a
+//! drop-in alternative to the ST7735 in `screen.rs` for installations that
+//! use an e-paper display instead of a live LCD.
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::Duration;
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*, primitives::Rectangle};
+use embedded_hal_bus::spi::{ExclusiveDevice, NoDelay};
+use esp_hal::{gpio::{Input, Output}, spi::master::Spi, Async};
+use esp_println::println;
+use ssd1680::{driver::Ssd1680, graphics::Display2in13};
+
+use esp32_drawer::{GridColor, RefreshPolicy, RefreshTarget, ScreenSignal};
+
+impl GridColor for BinaryColor {
+    fn background() -> Self {
+        BinaryColor::Off
+    }
+
+    fn from_raw(color: u16) -> Self {
+        if color != 0 {
+            BinaryColor::On
+        } else {
+            BinaryColor::Off
+        }
+    }
+}
+
+type EpaperSpi = ExclusiveDevice<Spi<'static, Async>, Output<'static>, NoDelay>;
+
+/// Pairs the SSD1680's software framebuffer with the driver that pushes it
+/// over SPI, so together they satisfy `RefreshTarget`: drawing only ever
+/// touches the framebuffer, and `refresh` is the single point that talks
+/// to the panel.
+pub struct EpaperScreen {
+    display: Display2in13,
+    driver: Ssd1680<EpaperSpi, Output<'static>, Output<'static>, Input<'static>>,
+}
+
+impl EpaperScreen {
+    pub fn new(driver: Ssd1680<EpaperSpi, Output<'static>, Output<'static>, Input<'static>>) -> Self {
+        Self {
+            display: Display2in13::bw(),
+            driver,
+        }
+    }
+}
+
+impl Dimensions for EpaperScreen {
+    fn bounding_box(&self) -> Rectangle {
+        self.display.bounding_box()
+    }
+}
+
+impl DrawTarget for EpaperScreen {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<P>(&mut self, pixels: P) -> Result<(), Self::Error>
+    where
+        P: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.display.draw_iter(pixels)
+    }
+}
+
+impl RefreshTarget for EpaperScreen {
+    fn refresh(&mut self) -> Result<(), Self::Error> {
+        if let Err(e) = self.driver.update_bw_frame(self.display.buffer()) {
+            println!("epaper update error: {:?}\r\n", e);
+        }
+        if let Err(e) = self.driver.display_frame() {
+            println!("epaper display error: {:?}\r\n", e);
+        }
+        Ok(())
+    }
+
+    fn refresh_policy() -> RefreshPolicy {
+        // A full refresh visibly flashes the panel, so only push once a
+        // meaningful number of cells changed or half a second has passed,
+        // whichever comes first.
+        RefreshPolicy::Debounced {
+            threshold: 32,
+            interval: Duration::from_millis(500),
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn task_loop(panel: EpaperScreen, signal: &'static Signal<NoopRawMutex, ScreenSignal>) {
+    super::screen::run(panel, signal).await
+}
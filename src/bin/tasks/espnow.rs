@@ -0,0 +1,107 @@
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{Duration, Timer};
+use esp_println::println;
+use esp_wifi::esp_now::{EspNow, PeerInfo, BROADCAST_ADDRESS};
+
+use esp32_drawer::{ColoredCoordinate, EspNowOutbound, ScreenSignal, ESP_NOW_COORDS_PER_FRAME};
+
+use super::backend::SharedGridData;
+
+const OP_COORDINATE: u8 = 0;
+const OP_CLEAR: u8 = 1;
+
+/// Minimum spacing between outbound frames so a burst of `/data` POSTs
+/// can't saturate the radio.
+const SEND_INTERVAL: Duration = Duration::from_millis(20);
+
+pub type OutboundChannel = Channel<NoopRawMutex, EspNowOutbound, 4>;
+
+#[embassy_executor::task]
+pub async fn task_loop(
+    mut esp_now: EspNow<'static>,
+    origin: u8,
+    outbound: &'static OutboundChannel,
+    signal: &'static Signal<NoopRawMutex, ScreenSignal>,
+    grid_data: &'static SharedGridData,
+) {
+    println!("Starting espnow_loop\r\n");
+
+    // Ignore the error here: it only ever means the broadcast peer was
+    // already registered.
+    let _ = esp_now.add_peer(PeerInfo {
+        peer_address: BROADCAST_ADDRESS,
+        lmk: None,
+        channel: None,
+        encrypt: false,
+    });
+
+    loop {
+        match select(outbound.receive(), esp_now.receive_async()).await {
+            Either::First(message) => {
+                let mut frame = [0u8; 2 + ESP_NOW_COORDS_PER_FRAME * 4];
+                frame[0] = origin;
+                let len = match message {
+                    EspNowOutbound::Coordinates(coords) => {
+                        frame[1] = OP_COORDINATE;
+                        let mut len = 2;
+                        for coordinate in coords.into_iter().flatten() {
+                            let color = coordinate.color.to_be_bytes();
+                            frame[len] = coordinate.row as u8;
+                            frame[len + 1] = coordinate.col as u8;
+                            frame[len + 2] = color[0];
+                            frame[len + 3] = color[1];
+                            len += 4;
+                        }
+                        len
+                    }
+                    EspNowOutbound::Clear => {
+                        frame[1] = OP_CLEAR;
+                        2
+                    }
+                };
+                if let Err(e) = esp_now.send_async(&BROADCAST_ADDRESS, &frame[..len]).await {
+                    println!("espnow send error: {:?}\r\n", e);
+                }
+                Timer::after(SEND_INTERVAL).await;
+            }
+            Either::Second(received) => {
+                let data = received.data();
+                // Ignore anything shorter than the header, and our own
+                // broadcasts echoed back by the radio.
+                if data.len() < 2 || data[0] == origin {
+                    continue;
+                }
+                match data[1] {
+                    OP_CLEAR => {
+                        grid_data.lock().await.clear();
+                        signal.signal(ScreenSignal::Clear);
+                    }
+                    OP_COORDINATE => {
+                        let coords = decode_coordinates(&data[2..]);
+                        {
+                            let mut grid = grid_data.lock().await;
+                            for coordinate in coords.0.into_iter().flatten() {
+                                grid.set(coordinate);
+                            }
+                        }
+                        signal.signal(ScreenSignal::Coordinate(coords));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn decode_coordinates(payload: &[u8]) -> serde_big_array::Array<Option<ColoredCoordinate>, 256> {
+    let mut coords = [None; 256];
+    for (slot, quad) in coords.iter_mut().zip(payload.chunks_exact(4)) {
+        *slot = Some(ColoredCoordinate {
+            row: quad[0] as usize,
+            col: quad[1] as usize,
+            color: u16::from_be_bytes([quad[2], quad[3]]),
+        });
+    }
+    serde_big_array::Array(coords)
+}
@@ -1,23 +1,35 @@
 pub mod backend;
+#[cfg(feature = "epaper")]
+pub mod epaper;
+pub mod espnow;
 pub mod screen;
+#[cfg(feature = "w5500")]
+pub mod w5500;
 pub mod web;
+pub mod ws;
 
 use embassy_net::Stack;
 use embassy_time::{Duration, Timer};
 // use esp_hal::cpu_control::Stack;
 use esp_println::println;
 use esp_wifi::wifi::{
-    ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiStaDevice,
-    WifiState,
+    AccessPointConfiguration, ClientConfiguration, Configuration, WifiApDevice, WifiController,
+    WifiDevice, WifiEvent, WifiStaDevice, WifiState,
 };
 
+use crate::AP_SSID;
 use crate::PASSWORD;
 use crate::SSID;
 
+/// Number of consecutive failed STA connection attempts before the
+/// controller falls back to hosting its own access point.
+const AP_FALLBACK_ATTEMPTS: u8 = 5;
+
 #[embassy_executor::task]
 pub async fn connection(mut controller: WifiController<'static>) {
     println!("start connection task\r\n");
     println!("Device capabilities: {:#?}\r\n", controller.capabilities());
+    let mut failed_attempts: u8 = 0;
     loop {
         if esp_wifi::wifi::wifi_state() == WifiState::StaConnected {
             // wait until we're no longer connected
@@ -25,12 +37,24 @@ pub async fn connection(mut controller: WifiController<'static>) {
             Timer::after(Duration::from_millis(5000)).await
         }
         if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = Configuration::Client(ClientConfiguration {
+            let client_config = ClientConfiguration {
                 ssid: SSID.try_into().unwrap(),
                 password: PASSWORD.try_into().unwrap(),
                 ..Default::default()
-            });
-            controller.set_configuration(&client_config).unwrap();
+            };
+            let config = if failed_attempts >= AP_FALLBACK_ATTEMPTS {
+                println!("Too many failed connection attempts, starting SoftAP\r\n");
+                Configuration::Mixed(
+                    client_config,
+                    AccessPointConfiguration {
+                        ssid: AP_SSID.try_into().unwrap(),
+                        ..Default::default()
+                    },
+                )
+            } else {
+                Configuration::Client(client_config)
+            };
+            controller.set_configuration(&config).unwrap();
             println!("Starting wifi\r\n");
             controller.start_async().await.unwrap();
             println!("Wifi started!\r\n");
@@ -38,9 +62,18 @@ pub async fn connection(mut controller: WifiController<'static>) {
         println!("About to connect...\r\n");
 
         match controller.connect_async().await {
-            Ok(_) => println!("Wifi connected!\r\n"),
+            Ok(_) => {
+                println!("Wifi connected!\r\n");
+                failed_attempts = 0;
+            }
             Err(e) => {
                 println!("Failed to connect to wifi: {e:?}\r\n");
+                failed_attempts = failed_attempts.saturating_add(1);
+                if failed_attempts == AP_FALLBACK_ATTEMPTS {
+                    // Stop the controller so the next pass re-applies its
+                    // configuration, this time as `Configuration::Mixed`.
+                    let _ = controller.stop_async().await;
+                }
                 Timer::after(Duration::from_millis(5000)).await
             }
         }
@@ -51,3 +84,8 @@ pub async fn connection(mut controller: WifiController<'static>) {
 pub async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
     stack.run().await
 }
+
+#[embassy_executor::task]
+pub async fn net_task_ap(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    stack.run().await
+}
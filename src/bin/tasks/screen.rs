@@ -1,4 +1,6 @@
+use embassy_futures::select::{select, Either};
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::{Instant, Timer};
 use embedded_graphics::{
     geometry::Point,
     pixelcolor::{raw::RawU16, Rgb565},
@@ -10,38 +12,110 @@ use esp_hal::{gpio::Output, spi::master::Spi, Async};
 use esp_println::println;
 use st7735_lcd::ST7735;
 
-use esp32_drawer::ScreenSignal;
+use esp32_drawer::{GridColor, RefreshPolicy, RefreshTarget, ScreenSignal};
+
+impl GridColor for Rgb565 {
+    fn background() -> Self {
+        Rgb565::BLACK
+    }
+
+    fn from_raw(color: u16) -> Self {
+        Rgb565::from(RawU16::new(color))
+    }
+}
+
+impl<SPI, DC, RST> RefreshTarget for ST7735<SPI, DC, RST>
+where
+    ST7735<SPI, DC, RST>: DrawTarget<Color = Rgb565>,
+{
+    // The ST7735 writes each pixel straight to the glass over SPI, so
+    // there is nothing buffered to push.
+    fn refresh(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
 
 #[embassy_executor::task]
 pub async fn task_loop(
-    mut st7735: ST7735<
+    st7735: ST7735<
         ExclusiveDevice<Spi<'static, Async>, Output<'static>, NoDelay>,
         Output<'static>,
         Output<'static>,
     >,
     signal: &'static Signal<NoopRawMutex, ScreenSignal>,
 ) {
+    run(st7735, signal).await
+}
+
+/// Drives any panel that implements `RefreshTarget`, accumulating
+/// coordinate writes and pushing a refresh according to the panel's own
+/// `RefreshPolicy` instead of assuming a live, per-pixel display.
+pub(crate) async fn run<C, D>(mut target: D, signal: &'static Signal<NoopRawMutex, ScreenSignal>)
+where
+    C: GridColor,
+    D: RefreshTarget<Color = C>,
+{
     println!("Starting screen loop\r\n");
-    let _color = RawU16::from(Rgb565::RED).into_inner();
+    let mut changed_since_refresh = 0usize;
+    let mut last_refresh = Instant::now();
+
     loop {
-        let result = signal.wait().await;
+        let result = match D::refresh_policy() {
+            RefreshPolicy::Debounced { interval, .. } if changed_since_refresh > 0 => {
+                match select(signal.wait(), Timer::at(last_refresh + interval)).await {
+                    Either::First(result) => result,
+                    Either::Second(()) => {
+                        maybe_refresh(&mut target, &mut changed_since_refresh, &mut last_refresh);
+                        continue;
+                    }
+                }
+            }
+            _ => signal.wait().await,
+        };
         match result {
             ScreenSignal::Coordinate(coordinates) => {
-                // draw to screen
                 for coordinate in coordinates.into_iter().flatten() {
-                    let x = coordinate.1 * 2;
-                    let y = coordinate.0 * 2;
+                    let x = coordinate.col * 2;
+                    let y = coordinate.row * 2;
                     let rect = Rectangle::new(Point::new(x as i32, y as i32), Size::new(2, 2));
-                    if let Err(e) = st7735.fill_solid(&rect, Rgb565::RED) {
+                    if let Err(e) = target.fill_solid(&rect, C::from_raw(coordinate.color)) {
                         println!("Error writing pixel to screen: {:?}", e);
                     }
+                    changed_since_refresh += 1;
                 }
                 signal.reset();
+                maybe_refresh(&mut target, &mut changed_since_refresh, &mut last_refresh);
             }
             ScreenSignal::Clear => {
-                let _ = st7735.clear(Rgb565::BLACK);
+                let _ = target.clear(C::background());
+                if let Err(e) = target.refresh() {
+                    println!("Error refreshing screen: {:?}", e);
+                }
+                changed_since_refresh = 0;
+                last_refresh = Instant::now();
                 signal.reset();
             }
         }
     }
 }
+
+fn maybe_refresh<C, D>(target: &mut D, changed: &mut usize, last_refresh: &mut Instant)
+where
+    C: GridColor,
+    D: RefreshTarget<Color = C>,
+{
+    let due = match D::refresh_policy() {
+        RefreshPolicy::Immediate => true,
+        RefreshPolicy::Debounced {
+            threshold,
+            interval,
+        } => *changed >= threshold || last_refresh.elapsed() >= interval,
+    };
+    if due {
+        if let Err(e) = target.refresh() {
+            println!("Error refreshing screen: {:?}", e);
+        }
+        *changed = 0;
+        *last_refresh = Instant::now();
+    }
+}
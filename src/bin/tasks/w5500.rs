@@ -0,0 +1,31 @@
+//! Wired Ethernet transport: a WIZnet W5500 in MACRAW mode, wired up behind
+//! the `w5500` feature as a drop-in alternative to the Wi-Fi stacks in
+//! `mod.rs` for installations where Wi-Fi isn't available.
+use embassy_net_w5500::{Device, Runner, State};
+use embedded_hal_bus::spi::{ExclusiveDevice, NoDelay};
+use esp_hal::{
+    gpio::{Input, Output},
+    spi::master::Spi,
+    Async,
+};
+
+pub type W5500Spi = ExclusiveDevice<Spi<'static, Async>, Output<'static>, NoDelay>;
+pub type W5500Device = Device<'static>;
+
+#[embassy_executor::task]
+pub async fn net_task(runner: Runner<'static, W5500Spi, Input<'static>, Output<'static>>) {
+    runner.run().await
+}
+
+/// Brings up the W5500 driver and returns the `embassy_net` device plus the
+/// background runner task input; the caller builds a `Stack` from the
+/// device exactly as it would for a `WifiDevice`.
+pub async fn init(
+    spi: W5500Spi,
+    int: Input<'static>,
+    reset: Output<'static>,
+    mac_addr: [u8; 6],
+    state: &'static mut State<8, 8>,
+) -> (W5500Device, Runner<'static, W5500Spi, Input<'static>, Output<'static>>) {
+    embassy_net_w5500::new(mac_addr, state, spi, int, reset).await
+}
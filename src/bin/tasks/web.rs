@@ -1,46 +1,101 @@
 /// Core imports
+use alloc::boxed::Box;
 use core::fmt::Write;
-use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_net::{driver::Driver, tcp::TcpSocket, Stack};
 use embassy_time::{Duration, Timer};
 use embedded_io_async::Write as EmbeddedIoWrite;
 use esp_println::println;
-use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
+use esp_wifi::wifi::{WifiApDevice, WifiDevice, WifiStaDevice};
+use static_cell::StaticCell;
 
 /// Crate imports
 use crate::WEB_ENDPOINT;
+use esp32_drawer::assets::Assets;
 use esp32_drawer::buffer::{RequestBuffer, ResponseBuffer};
 use esp32_drawer::close_socket;
 use esp32_drawer::get_request;
+use esp32_drawer::router::{HandlerFuture, Router};
 use esp32_drawer::send_response_buffer;
 use esp32_drawer::write_response_status;
+use esp32_drawer::ParseStatus;
+use esp32_drawer::Request;
+use esp32_drawer::RequestError;
+
+/// Number of distinct assets served below; bump alongside any new
+/// `assets.register(..)` call.
+const ASSET_COUNT: usize = 2;
+
+/// Size of the request/response buffers `web::serve` hands to the router.
+const REQ_BUF: usize = 512;
+const RESP_BUF: usize = 512;
+
+fn assets() -> Assets<ASSET_COUNT> {
+    let mut assets = Assets::new();
+    assets
+        .register("/", "text/html", include_bytes!("../../index.html"))
+        .register("/css/style.css", "text/css", include_bytes!("../../css/style.css"));
+    assets
+}
 
-#[derive(Debug)]
-enum WebServeFile<'a> {
-    File(&'a [u8], &'a str),
-    NotFound,
+/// State the single route below needs: the static asset table.
+#[derive(Clone, Copy)]
+struct Ctx {
+    assets: &'static Assets<ASSET_COUNT>,
 }
 
-const INDEX: WebServeFile<'static> =
-    WebServeFile::File(include_bytes!("../../index.html"), "text/html");
-const CSS: WebServeFile<'static> =
-    WebServeFile::File(include_bytes!("../../css/style.css"), "text/css");
+fn web_router() -> Router<Ctx, Option<&'static [u8]>, REQ_BUF, RESP_BUF, 1> {
+    let mut router = Router::new();
+    router.route("GET", "/*", handle_get_asset);
+    router
+}
 
-fn path_to_file(path: &str) -> WebServeFile {
-    match path {
-        "/" => INDEX,
-        "/css/style.css" => CSS,
-        _ => WebServeFile::NotFound,
-    }
+/// Writes the response headers for the asset at `request.path` and hands
+/// back its body (if any) so the caller can stream it straight from flash
+/// instead of copying it through `response_buffer`.
+fn handle_get_asset<'a>(
+    request: Request<'a, REQ_BUF>,
+    response_buffer: &'a mut ResponseBuffer<RESP_BUF>,
+    ctx: Ctx,
+) -> HandlerFuture<'a, Option<&'static [u8]>> {
+    Box::pin(async move {
+        let if_none_match = request.header("If-None-Match");
+        let path = request.path.unwrap_or("");
+        ctx.assets.serve(path, if_none_match, response_buffer)
+    })
 }
 
 #[embassy_executor::task]
 pub async fn task_loop(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
+    serve(stack).await
+}
+
+/// Same web server bound to the SoftAP stack, so a client joining the
+/// device's own access point can reach the UI with no router involved.
+#[embassy_executor::task]
+pub async fn task_loop_ap(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    serve(stack).await
+}
+
+/// Same web server bound to a wired W5500 stack, for installations where
+/// Wi-Fi isn't available.
+#[cfg(feature = "w5500")]
+#[embassy_executor::task]
+pub async fn task_loop_eth(stack: &'static Stack<super::w5500::W5500Device>) {
+    serve(stack).await
+}
+
+async fn serve<D: Driver>(stack: &'static Stack<D>) {
     println!("Starting web_serve_loop\r\n");
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
     let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
     socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
 
+    type AssetsType = StaticCell<Assets<ASSET_COUNT>>;
+    static ASSETS: AssetsType = StaticCell::new();
+    let assets = &*ASSETS.init(assets());
+    let router = web_router();
+
     loop {
         let r = socket.accept(WEB_ENDPOINT).await;
 
@@ -51,69 +106,69 @@ pub async fn task_loop(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>
             continue;
         }
 
-        // let mut buffer = [0u8; 512];
-        let mut request_buffer = RequestBuffer::<512>::new();
-        let mut response_buffer = ResponseBuffer::<512>::new();
-        if let Err(e) = get_request(&mut socket, &mut request_buffer).await {
-            println!("web_serve_loop: {:?}", e);
-            continue;
-        }
-
-        let request_str = match core::str::from_utf8(request_buffer.buffer()) {
-            Ok(result) => result,
-            Err(e) => {
-                println!("web_serve_loop: {:?}", e);
-                continue;
-            }
-        };
-
-        let mut lines = request_str.split("\r\n");
-        let first_line = lines.next().unwrap_or("");
-        let mut parts = first_line.split(' ');
-        let method = parts.next().unwrap_or("");
-        let path = parts.next().unwrap_or("");
-
-        println!("web_serve_loop: {:?} {:?}\r\n", method, path);
-
-        let mut file_bytes = None;
-
-        match method {
-            "GET" => match path_to_file(path) {
-                WebServeFile::File(contents, content_type) => {
-                    write_response_status(&mut response_buffer, 200);
-                    let _ = write!(&mut response_buffer, "Content-Type: {}\r\n", content_type);
-                    let _ = write!(
-                        &mut response_buffer,
-                        "Content-Length: {}\r\n",
-                        contents.len()
-                    );
+        // Reuse this socket for multiple requests until the client asks to
+        // close, or it goes quiet for longer than `get_request`'s header
+        // timeout.
+        loop {
+            let mut request_buffer = RequestBuffer::<REQ_BUF>::new();
+            let mut response_buffer = ResponseBuffer::<RESP_BUF>::new();
+            let request_len = match get_request(&mut socket, &mut request_buffer).await {
+                Ok(len) => len,
+                Err(RequestError::Timeout) => {
+                    write_response_status(&mut response_buffer, 408);
                     let _ = write!(&mut response_buffer, "\r\n");
-                    file_bytes = Some(contents);
+                    send_response_buffer(&mut socket, response_buffer).await;
+                    break;
+                }
+                Err(e) => {
+                    println!("web_serve_loop: {:?}", e);
+                    break;
                 }
-                WebServeFile::NotFound => {
+            };
+
+            let mut request: Request<REQ_BUF> = Request::new();
+            request.set_request_buffer(&request_buffer, request_len);
+            if matches!(request.parse_request(), ParseStatus::Partial) {
+                println!("web_serve_loop: incomplete or malformed request headers\r\n");
+                break;
+            }
+
+            println!("web_serve_loop: {:?} {:?}\r\n", request.method, request.path);
+
+            let keep_alive = request
+                .header("Connection")
+                .map(|value| !value.eq_ignore_ascii_case("close"))
+            .unwrap_or(true);
+
+            let ctx = Ctx { assets };
+            let file_bytes = match router.dispatch(request, &mut response_buffer, ctx).await {
+                Some(bytes) => bytes,
+                None => {
                     write_response_status(&mut response_buffer, 404);
                     let _ = write!(&mut response_buffer, "\r\n");
+                    None
+                }
+            };
+
+            send_response_buffer(&mut socket, response_buffer).await;
+
+            if let Some(bytes) = file_bytes {
+                if let Err(e) = socket.write_all(bytes).await {
+                    println!("web_serve_loop write error: {:?}\r\n", e);
+                    break;
                 }
-            },
-            _ => {
-                write_response_status(&mut response_buffer, 404);
-                let _ = write!(&mut response_buffer, "\r\n");
             }
-        }
 
-        send_response_buffer(&mut socket, response_buffer).await;
+            let r = socket.flush().await;
+            if let Err(e) = r {
+                println!("web_serve_loop flush error: {:?}\r\n", e);
+            }
 
-        if let Some(bytes) = file_bytes {
-            if let Err(e) = socket.write_all(bytes).await {
-                println!("web_serve_loop write error: {:?}\r\n", e);
-                continue;
+            if !keep_alive {
+                break;
             }
         }
 
-        let r = socket.flush().await;
-        if let Err(e) = r {
-            println!("web_serve_loop flush error: {:?}\r\n", e);
-        }
         Timer::after(Duration::from_millis(500)).await;
         socket.close();
         Timer::after(Duration::from_millis(500)).await;
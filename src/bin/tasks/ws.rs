@@ -0,0 +1,117 @@
+/// Socket-level half of the WebSocket subsystem: the handshake response and
+/// frame read/write over a live `TcpSocket`. The protocol-only parts (the
+/// accept-key derivation and the frame header codec) live in
+/// `esp32_drawer::websocket` so they can be unit tested without a socket.
+use core::fmt::Write as CoreWrite;
+use embassy_net::tcp::TcpSocket;
+use embedded_io_async::Write as EmbeddedIoWrite;
+
+use esp32_drawer::buffer::ResponseBuffer;
+use esp32_drawer::websocket::{accept_key, decode_frame_header, encode_frame_header, unmask, FrameHeaderError, Opcode};
+use esp32_drawer::write_response_status;
+
+/// Writes the `101 Switching Protocols` handshake response for `client_key`
+/// into `response_buffer`.
+pub fn prepare_handshake<const S: usize>(client_key: &str, response_buffer: &mut ResponseBuffer<S>) {
+    let mut accept = [0u8; 28];
+    let accept = accept_key(client_key, &mut accept);
+
+    write_response_status(response_buffer, 101);
+    let _ = write!(response_buffer, "Upgrade: websocket\r\n");
+    let _ = write!(response_buffer, "Connection: Upgrade\r\n");
+    let _ = write!(response_buffer, "Sec-WebSocket-Accept: {}\r\n", accept);
+    let _ = write!(response_buffer, "\r\n");
+}
+
+/// Reads one WebSocket message off `socket` into `buffer`, unmasking the
+/// payload in place. Ping/Pong/Close are handled here directly (a Pong is
+/// sent for a Ping, and `Err` is returned on Close so the caller tears the
+/// connection down); only Text/Binary messages are handed back to the
+/// caller, as `(opcode, payload_len)` with the payload sitting at the front
+/// of `buffer`.
+pub async fn read_message<'a, const S: usize>(
+    socket: &mut TcpSocket<'a>,
+    buffer: &mut [u8; S],
+) -> Result<Option<(Opcode, usize)>, embassy_net::tcp::Error> {
+    let mut pos = 0;
+    let header = loop {
+        if pos >= buffer.len() {
+            return Ok(None);
+        }
+        let len = socket.read(&mut buffer[pos..]).await?;
+        if len == 0 {
+            return Err(embassy_net::tcp::Error::ConnectionReset);
+        }
+        pos += len;
+        match decode_frame_header(&buffer[..pos]) {
+            Ok(header) => break header,
+            Err(FrameHeaderError::Incomplete) => continue,
+        }
+    };
+
+    // A frame whose payload doesn't fit in `buffer` can't be read into it at
+    // all, let alone in place. Rather than stop filling partway through
+    // (leaving the rest of the payload sitting unread on the socket, so the
+    // next read_message call decodes it as a bogus frame header and
+    // desyncs framing for the rest of the connection), drain and discard
+    // it here and close per RFC 6455 with code 1009, Message Too Big.
+    if header.payload_len as usize > buffer.len() - header.header_len {
+        let mut remaining = header.payload_len as usize - (pos - header.header_len);
+        while remaining > 0 {
+            let len = socket.read(&mut buffer[..remaining.min(buffer.len())]).await?;
+            if len == 0 {
+                return Err(embassy_net::tcp::Error::ConnectionReset);
+            }
+            remaining -= len;
+        }
+        send_frame(socket, Opcode::Close, &1009u16.to_be_bytes()).await?;
+        return Err(embassy_net::tcp::Error::ConnectionReset);
+    }
+
+    let frame_end = header.header_len + header.payload_len as usize;
+    while pos < frame_end {
+        let len = socket.read(&mut buffer[pos..frame_end]).await?;
+        if len == 0 {
+            return Err(embassy_net::tcp::Error::ConnectionReset);
+        }
+        pos += len;
+    }
+
+    let payload_start = header.header_len;
+    if header.masked {
+        unmask(&mut buffer[payload_start..frame_end], header.mask);
+    }
+
+    match header.opcode {
+        Opcode::Ping => {
+            send_frame(socket, Opcode::Pong, &buffer[payload_start..frame_end]).await?;
+            Ok(None)
+        }
+        Opcode::Pong => Ok(None),
+        Opcode::Close => {
+            send_frame(socket, Opcode::Close, &[]).await?;
+            Err(embassy_net::tcp::Error::ConnectionReset)
+        }
+        Opcode::Text | Opcode::Binary => {
+            let payload_len = frame_end - payload_start;
+            buffer.copy_within(payload_start..frame_end, 0);
+            Ok(Some((header.opcode, payload_len)))
+        }
+        Opcode::Continuation | Opcode::Other(_) => Ok(None),
+    }
+}
+
+/// Writes one unmasked server-to-client frame, per RFC 6455 section 5.1.
+pub async fn send_frame<'a>(
+    socket: &mut TcpSocket<'a>,
+    opcode: Opcode,
+    payload: &[u8],
+) -> Result<(), embassy_net::tcp::Error> {
+    let mut header = [0u8; 10];
+    let header_len = encode_frame_header(&mut header, true, opcode, payload.len());
+    socket.write_all(&header[..header_len]).await?;
+    if !payload.is_empty() {
+        socket.write_all(payload).await?;
+    }
+    Ok(())
+}
@@ -1,23 +1,139 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+extern crate alloc;
+
+pub mod assets;
 pub mod buffer;
+pub mod router;
+pub mod udp;
+pub mod websocket;
 
 use core::fmt::Write as CoreWrite;
 use embassy_net::tcp::TcpSocket;
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::PixelColor};
 use embedded_io_async::Write;
 use esp_println::println;
+use serde::{Deserialize, Serialize};
 
 use crate::buffer::RequestBuffer;
 
+/// A single `(row, col)` cell on the 80x64 drawing grid.
+pub type Coordinate = (usize, usize);
+
+/// A cell plus the color it was drawn with, as an RGB565 value regardless
+/// of the panel's native color type (see `GridColor::from_raw`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColoredCoordinate {
+    pub row: usize,
+    pub col: usize,
+    pub color: u16,
+}
+
+/// Body of a `POST /data` request: a batch of colored coordinates to set.
+#[derive(Debug, Deserialize)]
+pub struct Coordinates(pub serde_big_array::Array<Option<ColoredCoordinate>, 256>);
+
+/// Pushed through the `screen` task's `Signal` to drive the display.
+#[derive(Debug, Clone, Copy)]
+pub enum ScreenSignal {
+    Coordinate(serde_big_array::Array<Option<ColoredCoordinate>, 256>),
+    Clear,
+}
+
+/// Max coordinates that fit in one ESP-NOW broadcast frame: a 1-byte
+/// origin tag plus a 1-byte op, leaving 248 bytes for 62 `(u8, u8, u16)`
+/// colored coordinates (4 bytes each).
+pub const ESP_NOW_COORDS_PER_FRAME: usize = 62;
+
+/// Coordinate batch queued by `backend` for the `espnow` task to broadcast
+/// to peer boards; rate-limited and capped to a single frame's worth.
+#[derive(Debug, Clone, Copy)]
+pub enum EspNowOutbound {
+    Coordinates([Option<ColoredCoordinate>; ESP_NOW_COORDS_PER_FRAME]),
+    Clear,
+}
+
+/// Maps the drawing grid's stored RGB565 color to a concrete panel's
+/// native color type, so `screen::run` never hardcodes a color like
+/// `Rgb565::RED`.
+pub trait GridColor: PixelColor {
+    fn background() -> Self;
+    fn from_raw(color: u16) -> Self;
+}
+
+/// How a panel wants buffered pixel writes pushed out to the glass.
+///
+/// Live panels (ST7735 over SPI) draw every pixel immediately and refresh
+/// on every write. E-paper panels can't do that cheaply, so they batch
+/// writes into a framebuffer and only push a refresh once enough cells
+/// changed or a debounce window has elapsed.
+#[derive(Debug, Clone, Copy)]
+pub enum RefreshPolicy {
+    Immediate,
+    Debounced {
+        threshold: usize,
+        interval: Duration,
+    },
+}
+
+/// A `DrawTarget` that additionally knows how and when to push its
+/// buffered writes out to the physical panel.
+pub trait RefreshTarget: DrawTarget {
+    /// Push any buffered pixel writes out to the physical panel. Panels
+    /// that draw immediately can make this a no-op.
+    fn refresh(&mut self) -> Result<(), Self::Error>;
+
+    /// Defaults to `Immediate`, matching the previous per-pixel behavior.
+    fn refresh_policy() -> RefreshPolicy {
+        RefreshPolicy::Immediate
+    }
+}
+
+/// Max headers kept per request; anything past this is silently dropped
+/// rather than rejecting the request, matching this crate's general
+/// tolerance for truncating oversized input instead of erroring on it.
+const MAX_HEADERS: usize = 32;
+
+/// Result of an incremental parse that may not have enough bytes yet, in
+/// the spirit of `httparse::Status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseStatus<T> {
+    /// Parsing finished; `T` is the parser's result.
+    Complete(T),
+    /// Not enough bytes yet to finish; the caller should read more.
+    Partial,
+}
+
+/// Finds the end of a request's header block, i.e. the byte offset just
+/// past the blank line terminating it. Scans raw bytes rather than
+/// requiring the whole slice to be valid UTF-8, so a binary body read in
+/// the same packet as the headers doesn't prevent finding them.
+pub fn parse_headers(bytes: &[u8]) -> ParseStatus<usize> {
+    match bytes.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => ParseStatus::Complete(i + 4),
+        None => ParseStatus::Partial,
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Request<'a, const S: usize> {
     buffer: Option<&'a RequestBuffer<S>>,
     rb: Option<&'a [u8]>,
+    len: Option<usize>,
     pub method: Option<&'a str>,
     pub path: Option<&'a str>,
-    pub headers: [Option<&'a str>; 32],
-    pub data: Option<&'a str>,
+    headers: [Option<(&'a str, &'a str)>; MAX_HEADERS],
+    header_count: usize,
+    /// The request body, as raw bytes: it isn't required to be valid UTF-8
+    /// (only the header region is).
+    pub data: Option<&'a [u8]>,
+    /// Set by `parse_request` when the request carries `Upgrade: websocket`
+    /// and a `Sec-WebSocket-Key`, i.e. it's asking to switch protocols.
+    pub websocket_key: Option<&'a str>,
+    /// Set by `router::Router::dispatch` when the matched route's path has a
+    /// `:param` segment, to the corresponding segment of the request path.
+    pub param: Option<&'a str>,
 }
 
 impl<'a, const S: usize> Default for Request<'a, S> {
@@ -31,71 +147,288 @@ impl<'a, const S: usize> Request<'a, S> {
         Self {
             buffer: None,
             rb: None,
+            len: None,
             method: None,
             path: None,
-            headers: [None; 32],
+            headers: [None; MAX_HEADERS],
+            header_count: 0,
             data: None,
+            websocket_key: None,
+            param: None,
         }
     }
 
-    pub fn set_request_buffer(&mut self, buffer: &'a RequestBuffer<S>) {
+    /// `len` is the number of bytes at the front of `buffer` that `get_request`
+    /// actually filled in (header plus body); the rest is stale or zeroed
+    /// padding left over from a previous request.
+    pub fn set_request_buffer(&mut self, buffer: &'a RequestBuffer<S>, len: usize) {
         self.buffer = Some(buffer);
+        self.len = Some(len);
         self.set_buffer();
     }
 
     fn set_buffer(&mut self) {
-        self.rb = Some(&self.buffer.unwrap().buf);
-    }
-
-    pub fn parse_request(&mut self) {
-        if let Some(buffer) = self.rb {
-            if let Ok(result) = core::str::from_utf8(buffer) {
-                let mut lines = result.split("\r\n");
-                let first_line = lines.next().unwrap_or("");
-                let mut parts = first_line.split(' ');
-                let method = parts.next().unwrap_or("");
-                let path = parts.next().unwrap_or("");
-                for (pos, line) in lines.by_ref().enumerate() {
-                    if line.is_empty() {
-                        break;
-                    }
-                    self.headers[pos] = Some(line);
-                }
-                let data = lines.next().unwrap_or("").trim_matches(char::from(0));
-                self.method = Some(method);
-                self.path = Some(path);
-                self.data = Some(data);
+        let len = self.len.unwrap_or(0);
+        self.rb = Some(&self.buffer.unwrap().buf[..len]);
+    }
+
+    /// Looks up a header's value by name, case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers[..self.header_count]
+            .iter()
+            .flatten()
+            .find_map(|(key, value)| key.eq_ignore_ascii_case(name).then_some(*value))
+    }
+
+    /// Parses the request line and headers out of the buffer set by
+    /// `set_request_buffer`. Only the header region needs to be valid ASCII;
+    /// the body is kept as raw bytes so a binary upload doesn't fail the
+    /// whole parse. Reports `ParseStatus::Partial` if the buffer doesn't
+    /// hold a complete header block (terminated by a blank line) or if that
+    /// block isn't ASCII.
+    pub fn parse_request(&mut self) -> ParseStatus<()> {
+        let Some(buffer) = self.rb else {
+            return ParseStatus::Partial;
+        };
+        let ParseStatus::Complete(header_end) = parse_headers(buffer) else {
+            return ParseStatus::Partial;
+        };
+        let Some(headers) = core::str::from_utf8(&buffer[..header_end])
+            .ok()
+            .filter(|h| h.is_ascii())
+        else {
+            return ParseStatus::Partial;
+        };
+
+        let mut lines = headers.split("\r\n");
+        let first_line = lines.next().unwrap_or("");
+        let mut parts = first_line.split(' ');
+        let method = parts.next().unwrap_or("");
+        let path = parts.next().unwrap_or("");
+
+        self.header_count = 0;
+        for line in lines {
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if self.header_count < self.headers.len() {
+                self.headers[self.header_count] = Some((name.trim(), value.trim()));
+                self.header_count += 1;
             }
         }
+
+        let is_upgrade = self
+            .header("Upgrade")
+            .map(|value| value.eq_ignore_ascii_case("websocket"))
+            .unwrap_or(false);
+        self.websocket_key = is_upgrade.then(|| self.header("Sec-WebSocket-Key")).flatten();
+
+        self.method = Some(method);
+        self.path = Some(path);
+        self.data = Some(&buffer[header_end..]);
+
+        ParseStatus::Complete(())
     }
 }
 
+/// Finds a header's value by name (case-insensitive) in the raw header
+/// block, i.e. everything up to but not including the blank line. Unlike
+/// `Request::header`, this works directly off a header block that hasn't
+/// been parsed into a `Request` yet (e.g. `get_request`'s own framing
+/// decisions).
+pub fn header_value<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.split("\r\n").find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// How long a single header read may go without progress before
+/// `get_request` gives up and reports `RequestError::Timeout`. Applied per
+/// `socket.read` call, not to the header block as a whole. Applies on every
+/// keep-alive cycle, so a client that goes idle mid-connection is evicted
+/// just as readily as one that's slow to send its first request.
+pub const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a single body read (`Content-Length` or chunked) may go without
+/// progress before `get_request` gives up with `RequestError::Timeout`.
+/// Applied per `socket.read` call, the same way `HEADER_READ_TIMEOUT` bounds
+/// each header read, so a client that sends valid headers and then stalls
+/// mid-body can't hold a connection open any longer than one that stalls
+/// before the headers finish.
+pub const BODY_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error from [`get_request`]: either the underlying socket failed, or the
+/// client didn't finish sending a request's headers within
+/// `HEADER_READ_TIMEOUT`.
+#[derive(Debug)]
+pub enum RequestError {
+    Tcp(embassy_net::tcp::Error),
+    Timeout,
+}
+
+impl From<embassy_net::tcp::Error> for RequestError {
+    fn from(e: embassy_net::tcp::Error) -> Self {
+        RequestError::Tcp(e)
+    }
+}
+
+/// Reads one HTTP request off `socket` into `request_buffer`, including its
+/// body: once the `\r\n\r\n` header terminator is found, keeps reading until
+/// `Content-Length` bytes of body have arrived, or decodes a
+/// `Transfer-Encoding: chunked` body in place. Returns the number of valid
+/// bytes now sitting at the front of `request_buffer`.
+///
+/// The header terminator is located with `parse_headers`, scanning raw
+/// bytes rather than requiring the whole read-so-far buffer to be valid
+/// UTF-8, so a binary body pulled in by the same `read` call as the tail of
+/// the headers doesn't prevent the headers from being found.
+///
+/// Each header read is bounded by `HEADER_READ_TIMEOUT`; a client that stops
+/// sending before its headers are complete gets `RequestError::Timeout`
+/// instead of tying up the socket forever. The timeout bounds a stall, not
+/// the total time taken to send the headers.
 pub async fn get_request<'a, const S: usize>(
     socket: &mut TcpSocket<'a>,
     request_buffer: &mut RequestBuffer<S>,
-) -> Result<(), embassy_net::tcp::Error> {
+) -> Result<usize, RequestError> {
     let mut pos = 0;
-    loop {
-        match socket.read(request_buffer.buffer_mut()).await {
-            Ok(0) => {
+    let header_end = loop {
+        if pos >= request_buffer.buffer().len() {
+            return Ok(pos);
+        }
+        let len = with_timeout(
+            HEADER_READ_TIMEOUT,
+            socket.read(&mut request_buffer.buffer_mut()[pos..]),
+        )
+        .await
+        .map_err(|_| RequestError::Timeout)??;
+        if len == 0 {
+            println!("AP read EOF\r\n");
+            return Err(RequestError::Tcp(embassy_net::tcp::Error::ConnectionReset));
+        }
+        pos += len;
+        if let ParseStatus::Complete(end) = parse_headers(&request_buffer.buffer()[..pos]) {
+            break end;
+        }
+    };
+
+    let headers = core::str::from_utf8(&request_buffer.buffer()[..header_end]).unwrap_or("");
+    let chunked = header_value(headers, "Transfer-Encoding")
+        .map(|value| value.eq_ignore_ascii_case("chunked"))
+        .unwrap_or(false);
+
+    if chunked {
+        return read_chunked_body(socket, request_buffer, header_end, pos).await;
+    }
+
+    if let Some(content_length) =
+        header_value(headers, "Content-Length").and_then(|value| value.parse::<usize>().ok())
+    {
+        let target = (header_end + content_length).min(request_buffer.buffer().len());
+        while pos < target {
+            let len = with_timeout(
+                BODY_READ_TIMEOUT,
+                socket.read(&mut request_buffer.buffer_mut()[pos..target]),
+            )
+            .await
+            .map_err(|_| RequestError::Timeout)??;
+            if len == 0 {
                 println!("AP read EOF\r\n");
-                return Err(embassy_net::tcp::Error::ConnectionReset);
+                return Err(RequestError::Tcp(embassy_net::tcp::Error::ConnectionReset));
             }
-            Ok(len) => match core::str::from_utf8(&request_buffer.buffer()[..(pos + len)]) {
-                Ok(to_print) => {
-                    if to_print.contains("\r\n\r\n") {
-                        break;
-                    }
-                    pos += len;
-                }
-                Err(e) => {
-                    println!("AP read error: {:?}\r\n", e);
-                }
-            },
-            Err(e) => return Err(e),
+            pos += len;
+        }
+    }
+
+    Ok(pos)
+}
+
+/// Reads the remainder of a `Transfer-Encoding: chunked` body and decodes
+/// it in place, compacting each chunk's data directly after the headers so
+/// the caller sees one contiguous body with no chunk framing left in it.
+///
+/// Scans raw bytes for the chunk-size line's `\r\n` and the terminating
+/// `0\r\n\r\n`, the same way `parse_headers` scans for the header
+/// terminator, rather than decoding the whole unprocessed tail as `str`: a
+/// chunk's data is allowed to contain non-ASCII bytes, and requiring the
+/// entire remainder to be valid UTF-8 would leave the loop unable to find a
+/// terminator that already arrived.
+async fn read_chunked_body<'a, const S: usize>(
+    socket: &mut TcpSocket<'a>,
+    request_buffer: &mut RequestBuffer<S>,
+    header_end: usize,
+    mut pos: usize,
+) -> Result<usize, RequestError> {
+    while !chunked_body_received(&request_buffer.buffer()[header_end..pos]) {
+        if pos >= request_buffer.buffer().len() {
+            break;
+        }
+        let len = with_timeout(
+            BODY_READ_TIMEOUT,
+            socket.read(&mut request_buffer.buffer_mut()[pos..]),
+        )
+        .await
+        .map_err(|_| RequestError::Timeout)??;
+        if len == 0 {
+            println!("AP read EOF\r\n");
+            return Err(RequestError::Tcp(embassy_net::tcp::Error::ConnectionReset));
+        }
+        pos += len;
+    }
+
+    Ok(decode_chunks(&mut request_buffer.buf, header_end, pos))
+}
+
+/// Whether a chunked body's terminating `0\r\n\r\n` has arrived yet,
+/// anywhere in `body` (the bytes read so far after the header block).
+/// Scans raw bytes rather than requiring `body` to be valid UTF-8, since a
+/// chunk's data is allowed to contain non-ASCII bytes.
+fn chunked_body_received(body: &[u8]) -> bool {
+    body.windows(5).any(|w| w == b"0\r\n\r\n")
+}
+
+/// Decodes a complete `Transfer-Encoding: chunked` body in place, compacting
+/// each chunk's data starting at `header_end` so the result is one
+/// contiguous body with no chunk framing left in it. Returns the offset one
+/// past the last byte written, i.e. the total length of the header block
+/// plus the decoded body.
+///
+/// Scans raw bytes for each chunk-size line's `\r\n`, the same way
+/// `parse_headers` scans for the header terminator, instead of decoding the
+/// whole remaining slice as `str`: a chunk's data is allowed to contain
+/// non-ASCII bytes, which would otherwise break decoding every chunk after
+/// the first one that isn't plain ASCII.
+fn decode_chunks(buf: &mut [u8], header_end: usize, pos: usize) -> usize {
+    let mut read_pos = header_end;
+    let mut write_pos = header_end;
+    loop {
+        let Some(line_end) = buf[read_pos..pos].windows(2).position(|w| w == b"\r\n") else {
+            break;
+        };
+        let Ok(size_text) = core::str::from_utf8(&buf[read_pos..read_pos + line_end]) else {
+            break;
+        };
+        let Ok(chunk_len) = usize::from_str_radix(size_text.trim(), 16) else {
+            break;
         };
+        let data_start = read_pos + line_end + 2;
+        if chunk_len == 0 {
+            break;
+        }
+        let data_end = data_start + chunk_len;
+        if data_end > pos {
+            break;
+        }
+        buf.copy_within(data_start..data_end, write_pos);
+        write_pos += chunk_len;
+        read_pos = data_end + 2;
     }
-    Ok(())
+
+    write_pos
 }
 
 pub fn write_response_status<const S: usize>(
@@ -104,9 +437,13 @@ pub fn write_response_status<const S: usize>(
 ) {
     let mut status: &str = "";
     match status_code {
+        101 => status = "HTTP/1.1 101 Switching Protocols\r\n",
         200 => status = "HTTP/1.1 200 OK\r\n",
+        204 => status = "HTTP/1.1 204 No Content\r\n",
+        304 => status = "HTTP/1.1 304 Not Modified\r\n",
         500 => status = "HTTP/1.1 500 Internal Server Error\r\n",
         404 => status = "HTTP/1.1 404 Not Found\r\n",
+        408 => status = "HTTP/1.1 408 Request Timeout\r\n",
         _ => {}
     }
     if let Err(e) = write!(response_buffer, "{}", status) {
@@ -114,15 +451,86 @@ pub fn write_response_status<const S: usize>(
     }
 }
 
-pub fn write_response_headers<const S: usize>(response_buffer: &mut buffer::ResponseBuffer<S>) {
-    if let Err(e) = write!(response_buffer, "Access-Control-Allow-Origin: *\r\n") {
-        println!("Error writing response headers: {:?}", e);
+/// CORS policy: which origins the API answers with explicit
+/// `Access-Control-*` headers for, and what a preflight may assume about
+/// the methods/headers/cache lifetime of the real request that follows.
+/// Echoing a single matched origin (rather than a blanket `*`) is what lets
+/// a browser send credentialed requests.
+#[derive(Debug, Clone, Copy)]
+pub struct CorsConfig<'a> {
+    pub allowed_origins: &'a [&'a str],
+    pub allowed_methods: &'a str,
+    pub allowed_headers: &'a str,
+    pub max_age: u32,
+}
+
+impl<'a> CorsConfig<'a> {
+    /// Finds `origin` in the allowlist, returning it unchanged so the
+    /// caller can echo it straight back.
+    fn matching_origin(&self, origin: Option<&str>) -> Option<&'a str> {
+        let origin = origin?;
+        self.allowed_origins
+            .iter()
+            .find(|allowed| **allowed == origin)
+            .copied()
+    }
+}
+
+/// Writes `Access-Control-Allow-Origin` for `origin` if it's in `cors`'s
+/// allowlist, then the blank line terminating the header block. The header
+/// is omitted entirely (rather than falling back to `*`) when `origin`
+/// doesn't match, so the browser enforces same-origin as normal.
+pub fn write_response_headers<const S: usize>(
+    response_buffer: &mut buffer::ResponseBuffer<S>,
+    cors: &CorsConfig<'_>,
+    origin: Option<&str>,
+) {
+    if let Some(allowed) = cors.matching_origin(origin) {
+        if let Err(e) = write!(
+            response_buffer,
+            "Access-Control-Allow-Origin: {}\r\n",
+            allowed
+        ) {
+            println!("Error writing response headers: {:?}", e);
+        }
     }
     if let Err(e) = write!(response_buffer, "\r\n") {
         println!("Error writing response headers: {:?}", e);
     }
 }
 
+/// Writes a `204` preflight response for an `OPTIONS` request: the matched
+/// origin plus the allowed methods/headers/cache lifetime from `cors`.
+/// Nothing beyond the status line is written if `origin` doesn't match the
+/// allowlist, since a browser won't honor a preflight that doesn't name its
+/// origin anyway.
+pub fn write_preflight_response<const S: usize>(
+    response_buffer: &mut buffer::ResponseBuffer<S>,
+    cors: &CorsConfig<'_>,
+    origin: Option<&str>,
+) {
+    write_response_status(response_buffer, 204);
+    if let Some(allowed) = cors.matching_origin(origin) {
+        let _ = write!(
+            response_buffer,
+            "Access-Control-Allow-Origin: {}\r\n",
+            allowed
+        );
+        let _ = write!(
+            response_buffer,
+            "Access-Control-Allow-Methods: {}\r\n",
+            cors.allowed_methods
+        );
+        let _ = write!(
+            response_buffer,
+            "Access-Control-Allow-Headers: {}\r\n",
+            cors.allowed_headers
+        );
+        let _ = write!(response_buffer, "Access-Control-Max-Age: {}\r\n", cors.max_age);
+    }
+    let _ = write!(response_buffer, "\r\n");
+}
+
 pub async fn send_response_buffer<'a, const S: usize>(
     socket: &mut TcpSocket<'a>,
     response_buffer: buffer::ResponseBuffer<S>,
@@ -142,3 +550,189 @@ pub async fn close_socket<'a>(socket: &mut TcpSocket<'a>) {
     Timer::after(Duration::from_millis(500)).await;
     socket.abort();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_headers_reports_partial_without_terminator() {
+        assert_eq!(parse_headers(b"GET / HTTP/1.1\r\nHost: x"), ParseStatus::Partial);
+    }
+
+    #[test]
+    fn parse_headers_finds_terminator_past_binary_body() {
+        let mut bytes = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n".to_vec();
+        bytes.extend_from_slice(&[0xff, 0x00, 0xfe]);
+        let ParseStatus::Complete(end) = parse_headers(&bytes) else {
+            panic!("expected Complete");
+        };
+        assert_eq!(end, bytes.len() - 3);
+    }
+
+    #[test]
+    fn header_value_is_case_insensitive() {
+        let headers = "GET / HTTP/1.1\r\nContent-Type: text/plain\r\n";
+        assert_eq!(header_value(headers, "content-type"), Some("text/plain"));
+    }
+
+    #[test]
+    fn header_value_missing_returns_none() {
+        let headers = "GET / HTTP/1.1\r\nContent-Type: text/plain\r\n";
+        assert_eq!(header_value(headers, "X-Missing"), None);
+    }
+
+    #[test]
+    fn parse_request_extracts_method_path_and_headers() {
+        let mut buf = RequestBuffer::<128>::new();
+        let raw = b"GET /data?x=1 HTTP/1.1\r\nHost: board\r\nContent-Type: text/plain\r\n\r\n";
+        buf.buf[..raw.len()].copy_from_slice(raw);
+        let mut request = Request::<128>::new();
+        request.set_request_buffer(&buf, raw.len());
+        assert_eq!(request.parse_request(), ParseStatus::Complete(()));
+        assert_eq!(request.method, Some("GET"));
+        assert_eq!(request.path, Some("/data?x=1"));
+        assert_eq!(request.header("content-type"), Some("text/plain"));
+        assert_eq!(request.data, Some(&b""[..]));
+    }
+
+    #[test]
+    fn parse_request_reports_partial_without_full_header_block() {
+        let mut buf = RequestBuffer::<128>::new();
+        let raw = b"GET /data HTTP/1.1\r\nHost: boa";
+        buf.buf[..raw.len()].copy_from_slice(raw);
+        let mut request = Request::<128>::new();
+        request.set_request_buffer(&buf, raw.len());
+        assert_eq!(request.parse_request(), ParseStatus::Partial);
+    }
+
+    #[test]
+    fn parse_request_captures_websocket_upgrade_key() {
+        let mut buf = RequestBuffer::<128>::new();
+        let raw = b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: abc123\r\n\r\n";
+        buf.buf[..raw.len()].copy_from_slice(raw);
+        let mut request = Request::<128>::new();
+        request.set_request_buffer(&buf, raw.len());
+        assert_eq!(request.parse_request(), ParseStatus::Complete(()));
+        assert_eq!(request.websocket_key, Some("abc123"));
+    }
+
+    #[test]
+    fn chunked_body_not_received_before_terminator_arrives() {
+        assert!(!chunked_body_received(b"5\r\nhello\r\n"));
+    }
+
+    #[test]
+    fn chunked_body_received_once_terminator_arrives() {
+        assert!(chunked_body_received(b"5\r\nhello\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn chunked_body_received_detects_terminator_after_binary_chunk_data() {
+        // A chunk's data is allowed to contain bytes that aren't valid UTF-8
+        // on their own; detection must still find the terminator that
+        // follows them.
+        let mut body = b"2\r\n".to_vec();
+        body.extend_from_slice(&[0xff, 0xfe]);
+        body.extend_from_slice(b"\r\n0\r\n\r\n");
+        assert!(chunked_body_received(&body));
+    }
+
+    #[test]
+    fn decode_chunks_compacts_single_chunk_in_place() {
+        let mut buf = [0u8; 64];
+        let header_end = 10;
+        let body = b"5\r\nhello\r\n0\r\n\r\n";
+        buf[header_end..header_end + body.len()].copy_from_slice(body);
+        let pos = header_end + body.len();
+
+        let end = decode_chunks(&mut buf, header_end, pos);
+
+        assert_eq!(&buf[header_end..end], b"hello");
+    }
+
+    #[test]
+    fn decode_chunks_joins_multiple_chunks_in_place() {
+        let mut buf = [0u8; 64];
+        let header_end = 0;
+        let body = b"3\r\nfoo\r\n3\r\nbar\r\n0\r\n\r\n";
+        buf[header_end..header_end + body.len()].copy_from_slice(body);
+        let pos = header_end + body.len();
+
+        let end = decode_chunks(&mut buf, header_end, pos);
+
+        assert_eq!(&buf[header_end..end], b"foobar");
+    }
+
+    #[test]
+    fn decode_chunks_stops_at_first_incomplete_chunk() {
+        let mut buf = [0u8; 64];
+        let header_end = 0;
+        // Second chunk claims 10 bytes but only 3 have arrived.
+        let body = b"3\r\nfoo\r\na\r\nbar";
+        buf[header_end..header_end + body.len()].copy_from_slice(body);
+        let pos = header_end + body.len();
+
+        let end = decode_chunks(&mut buf, header_end, pos);
+
+        assert_eq!(&buf[header_end..end], b"foo");
+    }
+
+    fn test_cors() -> CorsConfig<'static> {
+        CorsConfig {
+            allowed_origins: &["http://192.168.4.1:8080"],
+            allowed_methods: "GET, POST, OPTIONS",
+            allowed_headers: "Content-Type",
+            max_age: 86400,
+        }
+    }
+
+    #[test]
+    fn response_headers_echo_matching_origin() {
+        let cors = test_cors();
+        let mut response_buffer = buffer::ResponseBuffer::<128>::new();
+        write_response_headers(&mut response_buffer, &cors, Some("http://192.168.4.1:8080"));
+        let headers = core::str::from_utf8(response_buffer.buffer()).unwrap();
+        assert!(headers.contains("Access-Control-Allow-Origin: http://192.168.4.1:8080"));
+    }
+
+    #[test]
+    fn response_headers_omit_allow_origin_for_unlisted_origin() {
+        let cors = test_cors();
+        let mut response_buffer = buffer::ResponseBuffer::<128>::new();
+        write_response_headers(&mut response_buffer, &cors, Some("http://evil.example"));
+        let headers = core::str::from_utf8(response_buffer.buffer()).unwrap();
+        assert!(!headers.contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn response_headers_omit_allow_origin_when_no_origin_sent() {
+        let cors = test_cors();
+        let mut response_buffer = buffer::ResponseBuffer::<128>::new();
+        write_response_headers(&mut response_buffer, &cors, None);
+        let headers = core::str::from_utf8(response_buffer.buffer()).unwrap();
+        assert!(!headers.contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn preflight_response_includes_cors_headers_for_matching_origin() {
+        let cors = test_cors();
+        let mut response_buffer = buffer::ResponseBuffer::<256>::new();
+        write_preflight_response(&mut response_buffer, &cors, Some("http://192.168.4.1:8080"));
+        let headers = core::str::from_utf8(response_buffer.buffer()).unwrap();
+        assert!(headers.starts_with("HTTP/1.1 204"));
+        assert!(headers.contains("Access-Control-Allow-Origin: http://192.168.4.1:8080"));
+        assert!(headers.contains("Access-Control-Allow-Methods: GET, POST, OPTIONS"));
+        assert!(headers.contains("Access-Control-Allow-Headers: Content-Type"));
+        assert!(headers.contains("Access-Control-Max-Age: 86400"));
+    }
+
+    #[test]
+    fn preflight_response_is_status_only_for_unlisted_origin() {
+        let cors = test_cors();
+        let mut response_buffer = buffer::ResponseBuffer::<256>::new();
+        write_preflight_response(&mut response_buffer, &cors, Some("http://evil.example"));
+        let headers = core::str::from_utf8(response_buffer.buffer()).unwrap();
+        assert_eq!(headers, "HTTP/1.1 204 No Content\r\n\r\n");
+    }
+}
@@ -0,0 +1,241 @@
+//! A declarative alternative to hand-writing `match request.method { ... }`
+//! in an accept loop: register `(method, path)` handlers once on a
+//! [`Router`], then call [`Router::dispatch`] with each parsed [`Request`].
+//!
+//! A route's path may end in a trailing `*` segment, matching however many
+//! path segments remain (including none), or contain a single `:param`
+//! segment, whose matched value is stashed in [`Request::param`] before the
+//! handler runs.
+//!
+//! A handler writes its own entire response (status line, any extra
+//! headers, CORS) rather than returning a status code for `dispatch` to
+//! write afterward: `ResponseBuffer` is append-only, so the status line has
+//! to be the first thing written, before a handler's own `Content-Type` or
+//! body, and `dispatch` only finds out the status after the handler has
+//! already run. `Ctx` carries whatever state a route needs (a shared grid,
+//! a CORS policy, ...) that a plain `fn` pointer can't capture as a
+//! closure. `R` is whatever else a handler needs to hand back to the
+//! caller beyond what it wrote into the buffer, e.g. a body to stream
+//! separately so it isn't copied into the buffer twice.
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+
+use crate::buffer::ResponseBuffer;
+use crate::Request;
+
+/// A handler's boxed return future, so `Router` can hold handlers whose
+/// async bodies are each a distinct, unnameable type in the same route
+/// table.
+pub type HandlerFuture<'a, R> = Pin<Box<dyn Future<Output = R> + 'a>>;
+
+/// One route's handler: receives the parsed request, the response buffer to
+/// write its response into, and `ctx`, and returns whatever the caller
+/// needs beyond what it wrote into the buffer. See the module docs for why
+/// a handler writes its own status line and headers instead of returning a
+/// status code.
+pub type Handler<Ctx, R, const REQ_S: usize, const RESP_S: usize> =
+    for<'a> fn(Request<'a, REQ_S>, &'a mut ResponseBuffer<RESP_S>, Ctx) -> HandlerFuture<'a, R>;
+
+struct Route<Ctx, R, const REQ_S: usize, const RESP_S: usize> {
+    method: &'static str,
+    path: &'static str,
+    handler: Handler<Ctx, R, REQ_S, RESP_S>,
+}
+
+/// A fixed-capacity `(method, path)` route table. `N` is the max number of
+/// routes it can hold; registrations past that are silently dropped,
+/// matching this crate's general tolerance for truncating oversized input
+/// instead of erroring on it.
+pub struct Router<Ctx, R, const REQ_S: usize, const RESP_S: usize, const N: usize> {
+    routes: [Option<Route<Ctx, R, REQ_S, RESP_S>>; N],
+    count: usize,
+}
+
+impl<Ctx, R, const REQ_S: usize, const RESP_S: usize, const N: usize> Default
+    for Router<Ctx, R, REQ_S, RESP_S, N>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Ctx, R, const REQ_S: usize, const RESP_S: usize, const N: usize>
+    Router<Ctx, R, REQ_S, RESP_S, N>
+{
+    pub fn new() -> Self {
+        Self {
+            routes: core::array::from_fn(|_| None),
+            count: 0,
+        }
+    }
+
+    /// Registers `handler` for `method`/`path`. Returns `self` so routes
+    /// can be chained, e.g. `router.route(..).route(..)`.
+    pub fn route(
+        &mut self,
+        method: &'static str,
+        path: &'static str,
+        handler: Handler<Ctx, R, REQ_S, RESP_S>,
+    ) -> &mut Self {
+        if self.count < self.routes.len() {
+            self.routes[self.count] = Some(Route {
+                method,
+                path,
+                handler,
+            });
+            self.count += 1;
+        }
+        self
+    }
+
+    /// Matches `request` against the registered routes in registration
+    /// order and runs the first match's handler, passing it `ctx`. Returns
+    /// `None` when nothing matches, so the caller can fall back to its own
+    /// `404`.
+    pub async fn dispatch<'a>(
+        &self,
+        mut request: Request<'a, REQ_S>,
+        response_buffer: &'a mut ResponseBuffer<RESP_S>,
+        ctx: Ctx,
+    ) -> Option<R> {
+        let method = request.method.unwrap_or("");
+        let path = request.path.unwrap_or("");
+
+        for route in self.routes[..self.count].iter().flatten() {
+            if route.method != method {
+                continue;
+            }
+            let Some(param) = match_route(route.path, path) else {
+                continue;
+            };
+            request.param = param;
+            return Some((route.handler)(request, response_buffer, ctx).await);
+        }
+
+        None
+    }
+}
+
+/// Matches a registered route pattern against a request path, segment by
+/// segment. A pattern segment of `*` must be the pattern's last segment and
+/// matches however many path segments remain, including none. A pattern
+/// segment starting with `:` captures the corresponding path segment.
+/// Returns `None` if the path doesn't match.
+fn match_route<'p>(pattern: &str, path: &'p str) -> Option<Option<&'p str>> {
+    let mut pattern_segs = pattern.split('/');
+    let mut path_segs = path.split('/');
+    let mut param = None;
+
+    loop {
+        match (pattern_segs.next(), path_segs.next()) {
+            (Some("*"), _) => return Some(param),
+            (Some(p), Some(s)) if p.starts_with(':') => param = Some(s),
+            (Some(p), Some(s)) if p == s => {}
+            (None, None) => return Some(param),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+    #[test]
+    fn exact_path_matches() {
+        assert_eq!(match_route("/clear", "/clear"), Some(None));
+    }
+
+    #[test]
+    fn exact_path_mismatch_is_rejected() {
+        assert_eq!(match_route("/clear", "/draw"), None);
+    }
+
+    #[test]
+    fn differing_segment_count_is_rejected() {
+        assert_eq!(match_route("/draw", "/draw/extra"), None);
+    }
+
+    #[test]
+    fn param_segment_captures_its_value() {
+        assert_eq!(match_route("/grid/:id", "/grid/7"), Some(Some("7")));
+    }
+
+    #[test]
+    fn trailing_wildcard_matches_remaining_segments() {
+        assert_eq!(
+            match_route("/static/*", "/static/css/app.css"),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn trailing_wildcard_matches_zero_segments() {
+        assert_eq!(match_route("/static/*", "/static"), Some(None));
+    }
+
+    /// Every `dispatch` test handler below resolves on its first poll, so a
+    /// waker that's never actually woken is enough to drive them.
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            raw_waker()
+        }
+        fn no_op(_: *const ()) {}
+        fn raw_waker() -> RawWaker {
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        unsafe { Waker::from_raw(raw_waker()) }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let core::task::Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+                return value;
+            }
+        }
+    }
+
+    fn ok_handler<'a>(
+        _request: Request<'a, 64>,
+        response_buffer: &'a mut ResponseBuffer<64>,
+        ctx: u32,
+    ) -> HandlerFuture<'a, u32> {
+        Box::pin(async move {
+            let _ = response_buffer.write(b"ok");
+            ctx
+        })
+    }
+
+    fn request_for<'a>(method: &'a str, path: &'a str) -> Request<'a, 64> {
+        let mut request = Request::new();
+        request.method = Some(method);
+        request.path = Some(path);
+        request
+    }
+
+    #[test]
+    fn dispatch_runs_matched_handler_with_ctx() {
+        let mut router: Router<u32, u32, 64, 64, 4> = Router::new();
+        router.route("GET", "/data", ok_handler);
+        let mut response_buffer = ResponseBuffer::<64>::new();
+        let result = block_on(router.dispatch(request_for("GET", "/data"), &mut response_buffer, 7));
+        assert_eq!(result, Some(7));
+        assert_eq!(response_buffer.buffer(), b"ok");
+    }
+
+    #[test]
+    fn dispatch_returns_none_when_nothing_matches() {
+        let mut router: Router<u32, u32, 64, 64, 4> = Router::new();
+        router.route("GET", "/data", ok_handler);
+        let mut response_buffer = ResponseBuffer::<64>::new();
+        let result = block_on(router.dispatch(request_for("GET", "/missing"), &mut response_buffer, 7));
+        assert_eq!(result, None);
+        assert_eq!(response_buffer.buffer(), b"");
+    }
+}
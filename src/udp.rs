@@ -0,0 +1,135 @@
+//! Compact binary framing for the low-latency UDP coordinate stream.
+//!
+//! Unlike the JSON `POST /data` body, a datagram carries a 1-byte op
+//! followed by `(row, col, color_hi, color_lo)` coordinate quads with no
+//! further framing, so a stroke can be pushed with no connection setup or
+//! teardown.
+use crate::ColoredCoordinate;
+
+pub const OP_COORDINATE: u8 = 0;
+pub const OP_CLEAR: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The datagram was empty; there was no op byte to read.
+    Empty,
+    UnknownOp(u8),
+}
+
+#[derive(Debug)]
+pub enum Frame<'a> {
+    Coordinates(CoordinateIter<'a>),
+    Clear,
+}
+
+/// Decodes `(row, col, color_hi, color_lo)` quads out of a coordinate
+/// frame's payload. A trailing partial quad (a datagram truncated
+/// mid-coordinate) is silently dropped rather than treated as an error.
+#[derive(Debug)]
+pub struct CoordinateIter<'a> {
+    quads: core::slice::ChunksExact<'a, u8>,
+}
+
+impl Iterator for CoordinateIter<'_> {
+    type Item = ColoredCoordinate;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.quads.next().map(|quad| ColoredCoordinate {
+            row: quad[0] as usize,
+            col: quad[1] as usize,
+            color: u16::from_be_bytes([quad[2], quad[3]]),
+        })
+    }
+}
+
+/// Decodes one UDP datagram into a `Frame`. Returns an error only for an
+/// empty datagram or an unrecognized op byte; a short payload is handled
+/// by simply yielding fewer coordinates.
+pub fn decode_frame(datagram: &[u8]) -> Result<Frame<'_>, FrameError> {
+    let (op, payload) = datagram.split_first().ok_or(FrameError::Empty)?;
+    match *op {
+        OP_COORDINATE => Ok(Frame::Coordinates(CoordinateIter {
+            quads: payload.chunks_exact(4),
+        })),
+        OP_CLEAR => Ok(Frame::Clear),
+        other => Err(FrameError::UnknownOp(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_datagram_is_an_error() {
+        assert_eq!(decode_frame(&[]).err(), Some(FrameError::Empty));
+    }
+
+    #[test]
+    fn unknown_op_is_rejected() {
+        assert_eq!(decode_frame(&[0xff, 1, 2]).err(), Some(FrameError::UnknownOp(0xff)));
+    }
+
+    #[test]
+    fn clear_op_decodes_with_no_payload() {
+        assert!(matches!(decode_frame(&[OP_CLEAR]), Ok(Frame::Clear)));
+    }
+
+    #[test]
+    fn clear_op_ignores_trailing_garbage() {
+        assert!(matches!(
+            decode_frame(&[OP_CLEAR, 9, 9, 9]),
+            Ok(Frame::Clear)
+        ));
+    }
+
+    #[test]
+    fn coordinate_quads_decode_in_order() {
+        let datagram = [OP_COORDINATE, 1, 2, 0x00, 0xff, 3, 4, 0xff, 0x00];
+        let coords: std::vec::Vec<ColoredCoordinate> = match decode_frame(&datagram).unwrap() {
+            Frame::Coordinates(iter) => iter.collect(),
+            Frame::Clear => panic!("expected coordinates"),
+        };
+        assert_eq!(
+            coords,
+            std::vec![
+                ColoredCoordinate {
+                    row: 1,
+                    col: 2,
+                    color: 0x00ff
+                },
+                ColoredCoordinate {
+                    row: 3,
+                    col: 4,
+                    color: 0xff00
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_partial_quad_is_dropped_not_errored() {
+        let datagram = [OP_COORDINATE, 1, 2, 0x00, 0xff, 3, 4];
+        let coords: std::vec::Vec<ColoredCoordinate> = match decode_frame(&datagram).unwrap() {
+            Frame::Coordinates(iter) => iter.collect(),
+            Frame::Clear => panic!("expected coordinates"),
+        };
+        assert_eq!(
+            coords,
+            std::vec![ColoredCoordinate {
+                row: 1,
+                col: 2,
+                color: 0x00ff
+            }]
+        );
+    }
+
+    #[test]
+    fn coordinate_op_with_empty_payload_yields_nothing() {
+        let coords: std::vec::Vec<ColoredCoordinate> = match decode_frame(&[OP_COORDINATE]).unwrap() {
+            Frame::Coordinates(iter) => iter.collect(),
+            Frame::Clear => panic!("expected coordinates"),
+        };
+        assert!(coords.is_empty());
+    }
+}
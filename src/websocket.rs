@@ -0,0 +1,403 @@
+//! Protocol primitives for RFC 6455 WebSockets: the handshake's
+//! accept-key derivation and the frame header codec. Reading/writing actual
+//! frames off a `TcpSocket` lives in `tasks::ws`, alongside the rest of the
+//! socket-handling code; this module only deals in plain byte slices so it
+//! can be unit tested like `udp`.
+
+/// Fixed GUID concatenated onto a client's `Sec-WebSocket-Key` before
+/// hashing, per RFC 6455 section 1.3.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Computes the `Sec-WebSocket-Accept` value for a client's
+/// `Sec-WebSocket-Key`: SHA-1 of the key concatenated with `GUID`,
+/// base64-encoded. `out` is scratch space for the encoded value.
+pub fn accept_key<'a>(client_key: &str, out: &'a mut [u8; 28]) -> &'a str {
+    let mut concatenated = [0u8; 128];
+    let key_bytes = client_key.as_bytes();
+    let total = (key_bytes.len() + GUID.len()).min(concatenated.len());
+    let key_len = key_bytes.len().min(total);
+    concatenated[..key_len].copy_from_slice(&key_bytes[..key_len]);
+    let guid_len = total - key_len;
+    concatenated[key_len..total].copy_from_slice(&GUID.as_bytes()[..guid_len]);
+
+    let digest = sha1(&concatenated[..total]);
+    base64_encode(&digest, out)
+}
+
+/// Minimal SHA-1 (RFC 3174), sized only for the short inputs a WebSocket
+/// handshake produces. Not suitable for any security-sensitive use.
+fn sha1(input: &[u8]) -> [u8; 20] {
+    let mut h0: u32 = 0x67452301;
+    let mut h1: u32 = 0xEFCDAB89;
+    let mut h2: u32 = 0x98BADCFE;
+    let mut h3: u32 = 0x10325476;
+    let mut h4: u32 = 0xC3D2E1F0;
+
+    let bit_len = (input.len() as u64) * 8;
+
+    let mut process = |block: &[u8; 64]| {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                block[i * 4],
+                block[i * 4 + 1],
+                block[i * 4 + 2],
+                block[i * 4 + 3],
+            ]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h0, h1, h2, h3, h4);
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | ((!b) & d), 0x5A827999u32),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1u32),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDCu32),
+                _ => (b ^ c ^ d, 0xCA62C1D6u32),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h0 = h0.wrapping_add(a);
+        h1 = h1.wrapping_add(b);
+        h2 = h2.wrapping_add(c);
+        h3 = h3.wrapping_add(d);
+        h4 = h4.wrapping_add(e);
+    };
+
+    let mut block = [0u8; 64];
+    let mut processed = 0usize;
+    while processed + 64 <= input.len() {
+        block.copy_from_slice(&input[processed..processed + 64]);
+        process(&block);
+        processed += 64;
+    }
+
+    // Final block(s): remaining bytes, then a 0x80 bit, zero padding, and
+    // the bit length as a trailing 8-byte big-endian integer. Needs a
+    // second block if the remainder doesn't leave room for both.
+    let remaining = input.len() - processed;
+    let mut tail = [0u8; 128];
+    tail[..remaining].copy_from_slice(&input[processed..]);
+    tail[remaining] = 0x80;
+    let tail_len = if remaining + 1 + 8 <= 64 { 64 } else { 128 };
+    tail[tail_len - 8..tail_len].copy_from_slice(&bit_len.to_be_bytes());
+
+    let mut offset = 0;
+    while offset < tail_len {
+        block.copy_from_slice(&tail[offset..offset + 64]);
+        process(&block);
+        offset += 64;
+    }
+
+    let mut digest = [0u8; 20];
+    digest[0..4].copy_from_slice(&h0.to_be_bytes());
+    digest[4..8].copy_from_slice(&h1.to_be_bytes());
+    digest[8..12].copy_from_slice(&h2.to_be_bytes());
+    digest[12..16].copy_from_slice(&h3.to_be_bytes());
+    digest[16..20].copy_from_slice(&h4.to_be_bytes());
+    digest
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `input` as standard (padded) base64 into `out`, returning the
+/// encoded text. `out` must be at least `4 * input.len().div_ceil(3)` bytes.
+fn base64_encode<'a>(input: &[u8], out: &'a mut [u8]) -> &'a str {
+    let mut o = 0;
+    let mut chunks = input.chunks_exact(3);
+    for chunk in &mut chunks {
+        let n = ((chunk[0] as u32) << 16) | ((chunk[1] as u32) << 8) | (chunk[2] as u32);
+        out[o] = BASE64_ALPHABET[((n >> 18) & 0x3F) as usize];
+        out[o + 1] = BASE64_ALPHABET[((n >> 12) & 0x3F) as usize];
+        out[o + 2] = BASE64_ALPHABET[((n >> 6) & 0x3F) as usize];
+        out[o + 3] = BASE64_ALPHABET[(n & 0x3F) as usize];
+        o += 4;
+    }
+    match chunks.remainder() {
+        [a] => {
+            let n = (*a as u32) << 16;
+            out[o] = BASE64_ALPHABET[((n >> 18) & 0x3F) as usize];
+            out[o + 1] = BASE64_ALPHABET[((n >> 12) & 0x3F) as usize];
+            out[o + 2] = b'=';
+            out[o + 3] = b'=';
+            o += 4;
+        }
+        [a, b] => {
+            let n = ((*a as u32) << 16) | ((*b as u32) << 8);
+            out[o] = BASE64_ALPHABET[((n >> 18) & 0x3F) as usize];
+            out[o + 1] = BASE64_ALPHABET[((n >> 12) & 0x3F) as usize];
+            out[o + 2] = BASE64_ALPHABET[((n >> 6) & 0x3F) as usize];
+            out[o + 3] = b'=';
+            o += 4;
+        }
+        _ => {}
+    }
+    core::str::from_utf8(&out[..o]).unwrap_or("")
+}
+
+/// A WebSocket frame's opcode (RFC 6455 section 5.2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+    Other(u8),
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xA => Opcode::Pong,
+            other => Opcode::Other(other),
+        }
+    }
+
+    fn to_bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+            Opcode::Other(bits) => bits,
+        }
+    }
+}
+
+/// A decoded frame header, i.e. everything before the (still masked)
+/// payload bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub masked: bool,
+    pub payload_len: u64,
+    /// Mask to XOR the payload with, valid only when `masked` is set.
+    pub mask: [u8; 4],
+    /// Bytes `bytes` occupied, so the payload starts at this offset.
+    pub header_len: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameHeaderError {
+    /// `bytes` doesn't yet hold the whole header; the caller should read
+    /// more and try again.
+    Incomplete,
+}
+
+/// Decodes a frame header from the start of `bytes`. Only the header is
+/// parsed here; the caller reads the payload separately once it knows
+/// `payload_len`.
+pub fn decode_frame_header(bytes: &[u8]) -> Result<FrameHeader, FrameHeaderError> {
+    if bytes.len() < 2 {
+        return Err(FrameHeaderError::Incomplete);
+    }
+    let fin = bytes[0] & 0x80 != 0;
+    let opcode = Opcode::from_bits(bytes[0] & 0x0F);
+    let masked = bytes[1] & 0x80 != 0;
+    let len7 = bytes[1] & 0x7F;
+
+    let (payload_len, mut header_len): (u64, usize) = match len7 {
+        126 => {
+            if bytes.len() < 4 {
+                return Err(FrameHeaderError::Incomplete);
+            }
+            (u16::from_be_bytes([bytes[2], bytes[3]]) as u64, 4)
+        }
+        127 => {
+            if bytes.len() < 10 {
+                return Err(FrameHeaderError::Incomplete);
+            }
+            let mut len_bytes = [0u8; 8];
+            len_bytes.copy_from_slice(&bytes[2..10]);
+            (u64::from_be_bytes(len_bytes), 10)
+        }
+        n => (n as u64, 2),
+    };
+
+    let mut mask = [0u8; 4];
+    if masked {
+        if bytes.len() < header_len + 4 {
+            return Err(FrameHeaderError::Incomplete);
+        }
+        mask.copy_from_slice(&bytes[header_len..header_len + 4]);
+        header_len += 4;
+    }
+
+    Ok(FrameHeader {
+        fin,
+        opcode,
+        masked,
+        payload_len,
+        mask,
+        header_len,
+    })
+}
+
+/// XORs `payload` with `mask` in place (RFC 6455 section 5.3).
+pub fn unmask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// Encodes a server-to-client frame header (always unmasked, per RFC 6455
+/// section 5.1) into `out`, returning the number of bytes written.
+pub fn encode_frame_header(out: &mut [u8; 10], fin: bool, opcode: Opcode, payload_len: usize) -> usize {
+    out[0] = (if fin { 0x80 } else { 0 }) | opcode.to_bits();
+    if payload_len < 126 {
+        out[1] = payload_len as u8;
+        2
+    } else if payload_len <= u16::MAX as usize {
+        out[1] = 126;
+        out[2..4].copy_from_slice(&(payload_len as u16).to_be_bytes());
+        4
+    } else {
+        out[1] = 127;
+        out[2..10].copy_from_slice(&(payload_len as u64).to_be_bytes());
+        10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha1_of_empty_string() {
+        assert_eq!(
+            sha1(b""),
+            [
+                0xda, 0x39, 0xa3, 0xee, 0x5e, 0x6b, 0x4b, 0x0d, 0x32, 0x55, 0xbf, 0xef, 0x95, 0x60,
+                0x18, 0x90, 0xaf, 0xd8, 0x07, 0x09
+            ]
+        );
+    }
+
+    #[test]
+    fn sha1_of_abc() {
+        assert_eq!(
+            sha1(b"abc"),
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+                0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+            ]
+        );
+    }
+
+    #[test]
+    fn sha1_spans_multiple_blocks() {
+        // 64 'a' bytes plus one more forces a second SHA-1 block.
+        let input = [b'a'; 65];
+        assert_eq!(
+            sha1(&input),
+            [
+                0x11, 0x65, 0x53, 0x26, 0xc7, 0x08, 0xd7, 0x03, 0x19, 0xbe, 0x26, 0x10, 0xe8, 0xa5,
+                0x7d, 0x9a, 0x5b, 0x95, 0x9d, 0x3b
+            ]
+        );
+    }
+
+    #[test]
+    fn rfc6455_example_accept_key() {
+        // The worked example from RFC 6455 section 1.3.
+        let mut out = [0u8; 28];
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ==", &mut out),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn decode_short_frame_header() {
+        let header = decode_frame_header(&[0x81, 0x05]).unwrap();
+        assert!(header.fin);
+        assert_eq!(header.opcode, Opcode::Text);
+        assert!(!header.masked);
+        assert_eq!(header.payload_len, 5);
+        assert_eq!(header.header_len, 2);
+    }
+
+    #[test]
+    fn decode_masked_frame_header() {
+        let bytes = [0x82, 0x84, 1, 2, 3, 4];
+        let header = decode_frame_header(&bytes).unwrap();
+        assert_eq!(header.opcode, Opcode::Binary);
+        assert!(header.masked);
+        assert_eq!(header.mask, [1, 2, 3, 4]);
+        assert_eq!(header.payload_len, 4);
+        assert_eq!(header.header_len, 6);
+    }
+
+    #[test]
+    fn decode_extended_16bit_length() {
+        let bytes = [0x82, 126, 0x01, 0x00];
+        let header = decode_frame_header(&bytes).unwrap();
+        assert_eq!(header.payload_len, 256);
+        assert_eq!(header.header_len, 4);
+    }
+
+    #[test]
+    fn decode_extended_64bit_length() {
+        let mut bytes = [0u8; 10];
+        bytes[0] = 0x82;
+        bytes[1] = 127;
+        bytes[2..10].copy_from_slice(&70_000u64.to_be_bytes());
+        let header = decode_frame_header(&bytes).unwrap();
+        assert_eq!(header.payload_len, 70_000);
+        assert_eq!(header.header_len, 10);
+    }
+
+    #[test]
+    fn decode_incomplete_header_reports_incomplete() {
+        assert_eq!(decode_frame_header(&[0x82]), Err(FrameHeaderError::Incomplete));
+        assert_eq!(
+            decode_frame_header(&[0x82, 126, 0x01]),
+            Err(FrameHeaderError::Incomplete)
+        );
+        assert_eq!(
+            decode_frame_header(&[0x82, 0x84, 1, 2, 3]),
+            Err(FrameHeaderError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn unmask_recovers_original_payload() {
+        let mut payload = [1u8, 2, 3, 4, 5];
+        let mask = [0xaa, 0xbb, 0xcc, 0xdd];
+        let original = payload;
+        unmask(&mut payload, mask);
+        unmask(&mut payload, mask);
+        assert_eq!(payload, original);
+    }
+
+    #[test]
+    fn encode_frame_header_picks_smallest_length_form() {
+        let mut out = [0u8; 10];
+        assert_eq!(encode_frame_header(&mut out, true, Opcode::Binary, 10), 2);
+        assert_eq!(encode_frame_header(&mut out, true, Opcode::Binary, 70_000), 10);
+    }
+}